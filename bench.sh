@@ -0,0 +1,53 @@
+#! /bin/bash
+# Measure taskmasterd idle CPU usage and control latency with many processes
+# Usage: ./bench.sh [numprocs] [idle seconds]
+
+NUMPROCS=${1:-1000}
+IDLE=${2:-10}
+ROOT=$(cd "$(dirname "$0")" && pwd)
+
+cargo build --workspace --release --manifest-path "$ROOT/Cargo.toml" || exit 1
+DIR=$(mktemp -d)
+trap 'HOME=$DIR "$ROOT/target/release/taskmasterctl" shutdown > /dev/null 2>&1; wait; rm -rf "$DIR"' EXIT
+
+cat > "$DIR/config.yml" << EOF2
+programs:
+  dummy:
+    cmd: "sleep 3600"
+    numprocs: $NUMPROCS
+    autostart: true
+    starttime: 1
+    autorestart: always
+EOF2
+
+cd "$DIR" || exit 1
+HOME=$DIR "$ROOT/target/release/taskmasterd" --nodaemon > "$DIR/taskmasterd.log" 2>&1 &
+PID=$!
+sleep 3
+
+cpu_ticks() {
+	awk '{ print $14 + $15 }' "/proc/$PID/stat"
+}
+
+BEFORE=$(cpu_ticks)
+sleep "$IDLE"
+AFTER=$(cpu_ticks)
+echo "idle cpu: $((AFTER - BEFORE)) ticks over ${IDLE}s ($(getconf CLK_TCK) ticks/s)"
+
+START=$(date +%s%N)
+for _ in $(seq 20); do
+	HOME=$DIR "$ROOT/target/release/taskmasterctl" status dummy > /dev/null
+done
+END=$(date +%s%N)
+echo "status latency: $(((END - START) / 20000)) us/request"
+
+status_pid() {
+	HOME=$DIR "$ROOT/target/release/taskmasterctl" status dummy | awk '$1 == "dummy-0:" { print $3 }'
+}
+
+OLD=$(status_pid)
+START=$(date +%s%N)
+kill "$OLD"
+while [ "$(status_pid)" = "$OLD" ]; do :; done
+END=$(date +%s%N)
+echo "exit latency: $(((END - START) / 1000)) us"
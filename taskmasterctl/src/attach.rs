@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Context, Result};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::termios::{tcgetattr, tcsetattr, InputFlags, LocalFlags, SetArg, Termios};
+use nix::unistd::{isatty, read};
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use taskmasterproto::{read_frame, write_frame, Request, Response};
+
+/// Byte sent by Ctrl-C when the terminal does not turn it into a signal
+const CTRL_C: u8 = 0x03;
+
+/// Terminal switched to raw input until dropped
+struct RawMode {
+    fd: RawFd,
+    saved: Termios,
+}
+
+impl RawMode {
+    fn enable(fd: RawFd) -> Result<Self> {
+        let saved = tcgetattr(fd).context("Failed to get the terminal attributes")?;
+        let mut raw = saved.clone();
+        // keystrokes go to the process as they are typed, the output is still post-processed
+        raw.local_flags &=
+            !(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG | LocalFlags::IEXTEN);
+        raw.input_flags &= !(InputFlags::IXON | InputFlags::ICRNL);
+        tcsetattr(fd, SetArg::TCSANOW, &raw).context("Failed to set the terminal attributes")?;
+        Ok(Self { fd, saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = tcsetattr(self.fd, SetArg::TCSANOW, &self.saved);
+    }
+}
+
+/// Forward the terminal to the attached process and print its output
+///
+/// A process running on a pseudo-terminal gets raw keystrokes, and Ctrl-C detaches. Otherwise
+/// the input is sent line by line, and Ctrl-C detaches by interrupting the client.
+pub fn run(unix_stream: &mut UnixStream, process: &str, tty: bool) -> Result<()> {
+    let stdin = std::io::stdin().as_raw_fd();
+    let _raw = match tty && isatty(stdin).unwrap_or(false) {
+        true => Some(RawMode::enable(stdin)?),
+        false => None,
+    };
+    eprint!("Attached to {}, Ctrl-C detaches\r\n", process);
+    let mut stdin_open = true;
+    let mut buffer = [0; 4096];
+    loop {
+        let mut fds = vec![PollFd::new(unix_stream.as_raw_fd(), PollFlags::POLLIN)];
+        if stdin_open {
+            fds.push(PollFd::new(stdin, PollFlags::POLLIN));
+        }
+        match poll(&mut fds, -1) {
+            Err(Errno::EINTR) => continue,
+            result => result.context("Failed to wait for input")?,
+        };
+        let ready = |fd: &PollFd| fd.revents().is_some_and(|revents| !revents.is_empty());
+        if ready(&fds[0]) {
+            match read_frame(unix_stream).context("Failed at reading from the unix stream")? {
+                Some(Response::Output { stderr, data, .. }) => {
+                    // the bytes go to the terminal as they are, escape sequences included
+                    if stderr {
+                        std::io::stderr().write_all(&data)?;
+                    } else {
+                        let mut stdout = std::io::stdout();
+                        stdout.write_all(&data)?;
+                        stdout.flush()?;
+                    }
+                }
+                Some(Response::Error { code, message }) => eprint!("{}: {}\r\n", code, message),
+                Some(response) => return Err(anyhow!("Unexpected response: {:?}", response)),
+                None => {
+                    eprint!("The daemon closed the connection\r\n");
+                    return Ok(());
+                }
+            }
+        }
+        if fds.get(1).is_some_and(ready) {
+            let n = read(stdin, &mut buffer).context("Failed to read stdin")?;
+            stdin_open = n > 0;
+            let input = &buffer[..n];
+            let detach = input.iter().position(|byte| *byte == CTRL_C);
+            let input = &input[..detach.unwrap_or(input.len())];
+            if !input.is_empty() {
+                let data = input.to_vec();
+                write_frame(unix_stream, &Request::Input { data })
+                    .context("Failed at writing onto the unix stream")?;
+            }
+            if detach.is_some() {
+                eprint!("\r\nDetached from {}\r\n", process);
+                return Ok(());
+            }
+        }
+    }
+}
@@ -1,11 +1,21 @@
+mod attach;
+mod wait;
+
 use anyhow::{Context, Result};
 use clap::*;
 use dirs::home_dir;
-use std::io::{Read, Write};
+use std::io::Write;
 use std::os::unix::net::UnixStream;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use taskmasterproto::{
+    read_frame, write_frame, ConfigDiff, Outcome, ProgramStatus, Request, Response, TargetError,
+    TargetResult, PROTOCOL_VERSION, SOCKET_PATH,
+};
+use wait::{Goal, WaitOpts};
 
-const SOCKET_PATH: &str = ".taskmasterd/taskmasterd.sock";
+/// How long to wait for the daemon to come back after an upgrade
+const UPGRADE_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Parser)]
 #[command(author, name = "taskmasterctl", about)]
@@ -18,118 +28,421 @@ struct Args {
 enum Command {
     /// Start processes
     Start {
-        /// The name of the processes to start, or all if not specified
-        #[clap(name = "name")]
+        /// The processes to start, or all if not specified
+        #[clap(name = "target")]
         processes: Vec<String>,
+        #[clap(flatten)]
+        wait: WaitOpts,
     },
     /// Stop processes
     Stop {
-        /// The name of the processes to stop, or all if not specified
-        #[clap(name = "name")]
+        /// The processes to stop, or all if not specified
+        #[clap(name = "target")]
         processes: Vec<String>,
+        #[clap(flatten)]
+        wait: WaitOpts,
     },
     /// Restart processes
     Restart {
-        /// The name of the processes to restart, or all if not specified
-        #[clap(name = "name")]
+        /// The processes to restart, or all if not specified
+        #[clap(name = "target")]
         processes: Vec<String>,
+        /// Restart in batches, each one once the previous one is running
+        #[clap(long)]
+        rolling: bool,
+        /// Restart all at once, even the programs whose restarts are rolling by default
+        #[clap(long, conflicts_with_all = ["rolling", "batch", "pause"])]
+        no_rolling: bool,
+        /// Number of processes restarted at once by a rolling restart
+        #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+        batch: Option<u32>,
+        /// Seconds to wait between the batches of a rolling restart
+        #[clap(long)]
+        pause: Option<u64>,
+        #[clap(flatten)]
+        wait: WaitOpts,
     },
     /// Get the status of processes
     Status {
-        /// The name of the processes to get the status of, or all if not specified
-        name: Vec<String>,
+        /// The processes to get the status of, or all if not specified
+        #[clap(name = "target")]
+        processes: Vec<String>,
+    },
+    /// Send a signal to processes
+    Signal {
+        /// The signal to send, like TERM or SIGUSR1
+        signal: String,
+        /// The processes to signal, or all if not specified
+        #[clap(name = "target")]
+        processes: Vec<String>,
+    },
+    /// Print the recent output of a process
+    Tail {
+        /// The process to print the output of
+        #[clap(name = "target")]
+        process: String,
+        /// Print stderr instead of stdout
+        #[clap(long)]
+        stderr: bool,
+        /// Keep printing the output as the process writes it
+        #[clap(short, long)]
+        follow: bool,
+        /// Number of lines to print
+        #[clap(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+    },
+    /// Attach the terminal to a process, Ctrl-C detaches
+    Fg {
+        /// The process to attach to
+        #[clap(name = "target")]
+        process: String,
+    },
+    /// Rotate the log files of processes
+    Rotate {
+        /// Only reopen the log files, after they were moved by another program
+        #[clap(long)]
+        reopen: bool,
+        /// The processes to rotate the logs of, or all if not specified
+        #[clap(name = "target")]
+        processes: Vec<String>,
+    },
+    /// Set the number of processes of a program, only starting or stopping the difference
+    Scale {
+        /// The program to scale
+        program: String,
+        /// The number of processes
+        numprocs: u32,
     },
-    /// Load a configuration file
+    /// Load a configuration file, applying the changes like update
     Load {
         /// The path to the configuration file
         path: PathBuf,
     },
-    /// Reload the configuration
-    Reload,
+    /// Show the programs added, changed and removed in the configuration file
+    Reread,
+    /// Show what loading a configuration file would change, without loading it
+    Diff {
+        /// The path to the configuration file
+        path: PathBuf,
+    },
+    /// Apply the changes of the configuration file, only restarting the changed programs
+    #[command(alias = "reload")]
+    Update,
     /// Shutdown the daemon
     Shutdown,
+    /// Replace the daemon with a new image of its executable, without stopping the processes
+    Upgrade,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let message = match args.command {
-        Some(Command::Start { processes }) => {
-            if processes.is_empty() {
-                "start".to_string()
+    let mut wait = None;
+    let mut follow = false;
+    let request = match args.command {
+        Some(Command::Start {
+            processes,
+            wait: opts,
+        }) => {
+            wait = opts.into_wait(Goal::Running);
+            Request::Start { targets: processes }
+        }
+        Some(Command::Stop {
+            processes,
+            wait: opts,
+        }) => {
+            wait = opts.into_wait(Goal::Stopped);
+            Request::Stop { targets: processes }
+        }
+        Some(Command::Restart {
+            processes,
+            rolling,
+            no_rolling,
+            batch,
+            pause,
+            wait: opts,
+        }) => {
+            wait = opts.into_wait(Goal::Running);
+            let rolling = if no_rolling {
+                Some(false)
             } else {
-                format!("start {}", processes.join(" "))
+                (rolling || batch.is_some() || pause.is_some()).then_some(true)
+            };
+            Request::Restart {
+                targets: processes,
+                rolling,
+                batch,
+                pause,
             }
         }
-        Some(Command::Stop { processes }) => {
-            if processes.is_empty() {
-                "stop".to_string()
-            } else {
-                format!("stop {}", processes.join(" "))
+        Some(Command::Status { processes }) => Request::Status { targets: processes },
+        Some(Command::Signal { signal, processes }) => Request::Signal {
+            signal,
+            targets: processes,
+        },
+        Some(Command::Tail {
+            process,
+            stderr,
+            follow: tail_follow,
+            lines,
+        }) => {
+            follow = tail_follow;
+            Request::Tail {
+                target: process,
+                stderr,
+                follow,
+                lines,
             }
         }
-        Some(Command::Restart { processes }) => {
-            if processes.is_empty() {
-                "restart".to_string()
-            } else {
-                format!("restart {}", processes.join(" "))
+        Some(Command::Fg { process }) => Request::Attach { target: process },
+        Some(Command::Rotate { reopen, processes }) => Request::Rotate {
+            targets: processes,
+            reopen,
+        },
+        Some(Command::Scale { program, numprocs }) => Request::Scale { program, numprocs },
+        Some(Command::Reread) => Request::Reread,
+        Some(Command::Update) => Request::Update,
+        Some(Command::Shutdown) => Request::Shutdown,
+        Some(Command::Upgrade) => Request::Upgrade,
+        None => return Ok(()),
+        Some(Command::Load { path }) => Request::Load {
+            path: path.canonicalize().context("Invalid path")?,
+        },
+        Some(Command::Diff { path }) => Request::Diff {
+            path: path.canonicalize().context("Invalid path")?,
+        },
+    };
+    let mut unix_stream = connect()?;
+    let response = send_request(&mut unix_stream, &request)?;
+    // only wait for the processes the request was carried out on
+    let processes = match &response {
+        Response::Results { results } => results.iter().flat_map(|r| r.processes.iter()).collect(),
+        _ => Vec::new(),
+    };
+    let with_outcome = |outcome: Outcome| {
+        processes
+            .iter()
+            .filter(|p| p.outcome == outcome)
+            .map(|p| p.name.clone())
+            .collect::<Vec<_>>()
+    };
+    let to_wait = with_outcome(Outcome::Ok);
+    let queued = with_outcome(Outcome::Queued);
+    let attached = match &response {
+        Response::Attached { process, tty } => Some((process.clone(), *tty)),
+        _ => None,
+    };
+    let upgrading = request == Request::Upgrade && response == Response::Ok;
+    let mut success = print_response(response)?;
+    if upgrading {
+        drop(unix_stream);
+        wait_upgrade()?;
+        return Ok(());
+    }
+    if let Some(wait) = wait {
+        success &= wait.run(&mut unix_stream, to_wait, queued)?;
+    }
+    if follow && success {
+        follow_output(&mut unix_stream)?;
+    }
+    if let Some((process, tty)) = attached {
+        attach::run(&mut unix_stream, &process, tty)?;
+    }
+    if !success {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Connect to the daemon and perform the handshake
+fn connect() -> Result<UnixStream> {
+    let socket_path = home_dir()
+        .context("Could not get home directory")?
+        .join(SOCKET_PATH);
+    let mut unix_stream = if let Ok(unix_stream) = UnixStream::connect(socket_path) {
+        unix_stream
+    } else {
+        return Err(anyhow::anyhow!(
+            "Could not connect to the daemon, is it running?"
+        ));
+    };
+    let hello = Request::Hello {
+        version: PROTOCOL_VERSION,
+    };
+    match send_request(&mut unix_stream, &hello)? {
+        Response::Hello { .. } => Ok(unix_stream),
+        Response::Error { code, message } => Err(anyhow::anyhow!("{}: {}", code, message)),
+        response => Err(anyhow::anyhow!(
+            "Unexpected handshake response: {:?}",
+            response
+        )),
+    }
+}
+
+/// Wait for the new image of the daemon to answer
+fn wait_upgrade() -> Result<()> {
+    let deadline = Instant::now() + UPGRADE_TIMEOUT;
+    loop {
+        // the previous image may accept the connection right before its exec
+        match connect().and_then(|mut unix_stream| {
+            send_request(
+                &mut unix_stream,
+                &Request::Status {
+                    targets: Vec::new(),
+                },
+            )
+        }) {
+            Ok(_) => {
+                println!("Upgraded");
+                return Ok(());
             }
+            Err(e) if Instant::now() >= deadline => {
+                return Err(e.context("The upgraded daemon did not answer"))
+            }
+            Err(_) => std::thread::sleep(Duration::from_millis(100)),
         }
-        Some(Command::Status { name }) => {
-            if name.is_empty() {
-                "status".to_string()
-            } else {
-                format!("status {}", name.join(" "))
+    }
+}
+
+fn send_request(unix_stream: &mut UnixStream, request: &Request) -> Result<Response> {
+    write_frame(unix_stream, request).context("Failed at writing onto the unix stream")?;
+    read_frame(unix_stream)
+        .context("Failed at reading from the unix stream")?
+        .context("The daemon closed the connection")
+}
+
+/// Print the response, returns whether the request fully succeeded
+fn print_response(response: Response) -> Result<bool> {
+    match response {
+        Response::Hello { .. }
+        | Response::Ok
+        | Response::Transition { .. }
+        | Response::Attached { .. } => Ok(true),
+        Response::Output {
+            stderr: true, data, ..
+        } => {
+            std::io::stderr().write_all(&data)?;
+            Ok(true)
+        }
+        Response::Output { data, .. } => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+            Ok(true)
+        }
+        Response::Results { results } => {
+            print_results(&results);
+            Ok(results.iter().all(TargetResult::is_success))
+        }
+        Response::Status {
+            programs,
+            not_found,
+        } => {
+            print_status(&programs);
+            for target in not_found.iter() {
+                println!("{}: {}", target, TargetError::NotFound);
             }
+            Ok(not_found.is_empty())
         }
-        Some(Command::Reload) => "reload".to_string(),
-        Some(Command::Shutdown) => "shutdown".to_string(),
-        None => "".to_string(),
-        Some(Command::Load { path }) => {
-            Path::new(&path.canonicalize().context("Invalid path")?)
-                .exists()
-                .then_some(())
-                .ok_or_else(|| anyhow::anyhow!("Invalid path"))?;
-            format!(
-                "load {}",
-                path.canonicalize()
-                    .expect("path should not have changed since check")
-                    .to_str()
-                    .context("Could not convert path to string/Invalid path")?
-            )
+        Response::Diff(diff) => {
+            print_diff(&diff);
+            Ok(true)
         }
-    };
-    if !message.is_empty() {
-        let socket_path = home_dir()
-            .context("Could not get home directory")?
-            .join(SOCKET_PATH);
-        let mut unix_stream = if let Ok(unix_stream) = UnixStream::connect(socket_path) {
-            unix_stream
-        } else {
-            return Err(anyhow::anyhow!(
-                "Could not connect to the daemon, is it running?"
-            ));
-        };
-        write_request_and_shutdown(&mut unix_stream, message)?;
-        read_from_stream(&mut unix_stream)?;
+        Response::Error { code, message } => Err(anyhow::anyhow!("{}: {}", code, message)),
     }
-    Ok(())
 }
 
-fn write_request_and_shutdown(unix_stream: &mut UnixStream, message: String) -> Result<()> {
-    unix_stream
-        .write_all(message.as_bytes())
-        .context("Failed at writing onto the unix stream")?;
-    unix_stream
-        .shutdown(std::net::Shutdown::Write)
-        .context("Could not shutdown writing on the stream")?;
-    Ok(())
+fn print_diff(diff: &ConfigDiff) {
+    if diff.is_empty() {
+        println!("No config changes");
+    }
+    for name in diff.added.iter() {
+        println!("{}: added", name);
+    }
+    for name in diff.changed.iter() {
+        println!("{}: changed", name);
+        for field in diff.fields.get(name).into_iter().flatten() {
+            println!("    {}", field);
+        }
+    }
+    for name in diff.removed.iter() {
+        println!("{}: removed", name);
+    }
+    for name in diff.added_groups.iter() {
+        println!("Group {}: added", name);
+    }
+    for name in diff.changed_groups.iter() {
+        println!("Group {}: changed", name);
+    }
+    for name in diff.removed_groups.iter() {
+        println!("Group {}: removed", name);
+    }
+    if !diff.restart.is_empty() {
+        println!("Processes to restart: {}", diff.restart.join(", "));
+    }
+    if !diff.stop.is_empty() {
+        println!("Processes to stop: {}", diff.stop.join(", "));
+    }
 }
 
-fn read_from_stream(unix_stream: &mut UnixStream) -> Result<()> {
-    let mut response = String::new();
-    unix_stream
-        .read_to_string(&mut response)
-        .context("Failed at reading from the unix stream")?;
-    println!("{}", response);
+/// Print the output of a tailed process until the daemon closes the connection
+fn follow_output(unix_stream: &mut UnixStream) -> Result<()> {
+    while let Some(response) =
+        read_frame(unix_stream).context("Failed at reading from the unix stream")?
+    {
+        print_response(response)?;
+    }
     Ok(())
 }
+
+fn print_results(results: &[TargetResult]) {
+    for result in results {
+        if let Some(error) = &result.error {
+            println!("{}: {}", result.target, error);
+        }
+        for process in result.processes.iter() {
+            println!("{}: {}", process.name, process.outcome);
+        }
+    }
+}
+
+/// Print the status of each program, the members of a group indented under it
+fn print_status(programs: &[ProgramStatus]) {
+    let width = 10;
+    let mut group = None;
+    for program in programs {
+        if program.group.is_some() && program.group != group {
+            println!("Group {}:", program.group.as_deref().unwrap_or_default());
+        }
+        group = program.group.clone();
+        let indent = if group.is_some() { "  " } else { "" };
+        println!("{}Job status {}:", indent, program.name);
+        if program.processes.is_empty() {
+            println!("{}No process running", indent);
+        }
+        for process in program.processes.iter() {
+            let pid = process
+                .pid
+                .map_or_else(|| "N/A".to_string(), |pid| pid.to_string());
+            match &process.notice {
+                Some(notice) => println!(
+                    "{}{}: pid: {:>width$} - {} - {}",
+                    indent, process.name, pid, process.description, notice
+                ),
+                None => println!(
+                    "{}{}: pid: {:>width$} - {}",
+                    indent, process.name, pid, process.description
+                ),
+            }
+            if !process.orphans.is_empty() {
+                let orphans = process.orphans.iter().map(i32::to_string);
+                println!(
+                    "{}{}: orphans: {}",
+                    indent,
+                    process.name,
+                    orphans.collect::<Vec<_>>().join(", ")
+                );
+            }
+        }
+        println!();
+    }
+}
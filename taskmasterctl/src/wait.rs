@@ -0,0 +1,160 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Args;
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+use taskmasterproto::{read_frame, write_frame, Health, ProcessState, Request, Response};
+
+#[derive(Args)]
+pub struct WaitOpts {
+    /// Wait until the processes reach the requested state
+    #[clap(long)]
+    wait: bool,
+    /// Give up waiting after this many seconds
+    #[clap(long, requires = "wait")]
+    timeout: Option<u64>,
+}
+
+/// State the processes should reach
+#[derive(Clone, Copy)]
+pub enum Goal {
+    /// Running past their start time, and healthy if they have health checks
+    Running,
+    /// Not running anymore
+    Stopped,
+}
+
+pub struct Wait {
+    goal: Goal,
+    timeout: Option<Duration>,
+}
+
+enum Progress {
+    Pending,
+    Reached,
+    Failed,
+}
+
+impl WaitOpts {
+    pub fn into_wait(self, goal: Goal) -> Option<Wait> {
+        self.wait.then(|| Wait {
+            goal,
+            timeout: self.timeout.map(Duration::from_secs),
+        })
+    }
+}
+
+impl Goal {
+    fn progress(&self, state: ProcessState, health: Option<Health>) -> Progress {
+        match (self, state) {
+            (Goal::Running, ProcessState::Running) => match health {
+                Some(Health::Starting | Health::Unhealthy) => Progress::Pending,
+                Some(Health::Healthy) | None => Progress::Reached,
+            },
+            (
+                Goal::Running,
+                ProcessState::Starting | ProcessState::Stopping | ProcessState::Backoff,
+            ) => Progress::Pending,
+            (Goal::Running, _) => Progress::Failed,
+            (Goal::Stopped, ProcessState::Backoff) => Progress::Pending,
+            (Goal::Stopped, state) if state.is_running() => Progress::Pending,
+            (Goal::Stopped, _) => Progress::Reached,
+        }
+    }
+}
+
+impl Wait {
+    /// Follow `processes` until they all reach the goal
+    ///
+    /// The `queued` processes wait for their turn in a rolling restart or for their
+    /// dependencies, their state only counts once they started, stopped or were given up on,
+    /// and waiting stops when one of the processes fails while others are still queued.
+    ///
+    /// Returns `false` if one of them failed to reach it, or if the timeout expired.
+    pub fn run(
+        &self,
+        unix_stream: &mut UnixStream,
+        processes: Vec<String>,
+        queued: Vec<String>,
+    ) -> Result<bool> {
+        if processes.is_empty() && queued.is_empty() {
+            return Ok(true);
+        }
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut pending: HashSet<String> = processes.into_iter().chain(queued.clone()).collect();
+        write_frame(
+            unix_stream,
+            &Request::Watch {
+                targets: pending.iter().cloned().collect(),
+            },
+        )
+        .context("Failed at writing onto the unix stream")?;
+        let mut queued: HashSet<String> = queued.into_iter().collect();
+        let mut success = true;
+        while !pending.is_empty() {
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Ok(timed_out(&pending));
+                }
+                unix_stream.set_read_timeout(Some(remaining))?;
+            }
+            let response = match read_frame(unix_stream) {
+                Ok(Some(response)) => response,
+                Ok(None) => return Err(anyhow!("The daemon closed the connection")),
+                Err(e) if is_timeout(&e) => return Ok(timed_out(&pending)),
+                Err(e) => return Err(e).context("Failed at reading from the unix stream"),
+            };
+            match response {
+                Response::Transition { process } => {
+                    println!("{}: {}", process.name, process.description);
+                    if queued.contains(&process.name) {
+                        if matches!(self.goal, Goal::Running)
+                            && !matches!(
+                                process.state,
+                                ProcessState::Starting
+                                    | ProcessState::Stopping
+                                    | ProcessState::Fatal
+                            )
+                        {
+                            continue;
+                        }
+                        queued.remove(&process.name);
+                    }
+                    match self.goal.progress(process.state, process.health) {
+                        Progress::Pending => {}
+                        Progress::Reached => {
+                            pending.remove(&process.name);
+                        }
+                        Progress::Failed if !queued.is_empty() => {
+                            let mut queued = queued.into_iter().collect::<Vec<_>>();
+                            queued.sort();
+                            println!("Given up on {}", queued.join(" "));
+                            return Ok(false);
+                        }
+                        Progress::Failed => {
+                            pending.remove(&process.name);
+                            success = false;
+                        }
+                    }
+                }
+                Response::Error { code, message } => return Err(anyhow!("{}: {}", code, message)),
+                response => return Err(anyhow!("Unexpected response: {:?}", response)),
+            }
+        }
+        Ok(success)
+    }
+}
+
+fn is_timeout(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut))
+}
+
+fn timed_out(pending: &HashSet<String>) -> bool {
+    let mut pending = pending.iter().cloned().collect::<Vec<_>>();
+    pending.sort();
+    println!("Timed out waiting for {}", pending.join(" "));
+    false
+}
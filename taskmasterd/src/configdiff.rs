@@ -0,0 +1,102 @@
+use serde_yaml::{Mapping, Value};
+
+/// Fields whose values may be secrets, so only the names of their changed keys are shown
+const HIDDEN: &[&str] = &["env"];
+
+/// Describe the fields that differ between two versions of the config of a program, like
+/// `numprocs 2 -> 4` or `env.ANSWER changed`
+pub fn fields(old: &Value, new: &Value) -> Vec<String> {
+    let mut changes = Vec::new();
+    compare("", Some(old), Some(new), false, &mut changes);
+    changes
+}
+
+fn compare(
+    field: &str,
+    old: Option<&Value>,
+    new: Option<&Value>,
+    hidden: bool,
+    changes: &mut Vec<String>,
+) {
+    if old == new {
+        return;
+    }
+    let empty = Mapping::new();
+    let old_map = old.map_or(Some(&empty), Value::as_mapping);
+    let new_map = new.map_or(Some(&empty), Value::as_mapping);
+    if let (Some(old_map), Some(new_map)) = (old_map, new_map) {
+        let mut keys = old_map
+            .keys()
+            .chain(new_map.keys().filter(|key| !old_map.contains_key(*key)))
+            .collect::<Vec<_>>();
+        keys.sort_by_key(|key| show(key));
+        for key in keys {
+            let name = match field {
+                "" => show(key),
+                _ => format!("{}.{}", field, show(key)),
+            };
+            let hidden = hidden || HIDDEN.contains(&name.as_str());
+            compare(&name, old_map.get(key), new_map.get(key), hidden, changes);
+        }
+        return;
+    }
+    changes.push(match (hidden, old, new) {
+        (true, None, _) => format!("{} added", field),
+        (true, _, None) => format!("{} removed", field),
+        (true, _, _) => format!("{} changed", field),
+        (false, old, new) => format!("{} {} -> {}", field, show_set(old), show_set(new)),
+    });
+}
+
+fn show_set(value: Option<&Value>) -> String {
+    value.map_or_else(|| "unset".to_string(), show)
+}
+
+/// Value as it could be written in the config, on a single line
+fn show(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(bool) => bool.to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::String(string) => string.clone(),
+        Value::Sequence(sequence) => {
+            let items = sequence.iter().map(show).collect::<Vec<_>>();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Mapping(mapping) => {
+            let entries = mapping
+                .iter()
+                .map(|(key, value)| format!("{}: {}", show(key), show(value)))
+                .collect::<Vec<_>>();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Value::Tagged(tagged) => format!("{} {}", tagged.tag, show(&tagged.value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields() {
+        let old: Value = serde_yaml::from_str(
+            "cmd: sleep 1\nnumprocs: 2\nstdout: /tmp/out\nenv:\n  ANSWER: 42\n  KEPT: 1\n",
+        )
+        .unwrap();
+        let new: Value = serde_yaml::from_str(
+            "cmd: sleep 1\nnumprocs: 4\nexitcodes: [0, 2]\nenv:\n  ANSWER: 43\n  KEPT: 1\n  NEW: 1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            fields(&old, &new),
+            [
+                "env.ANSWER changed",
+                "env.NEW added",
+                "exitcodes unset -> [0, 2]",
+                "numprocs 2 -> 4",
+                "stdout /tmp/out -> unset",
+            ]
+        );
+    }
+}
@@ -2,13 +2,14 @@ use crate::main_loop;
 use anyhow::{Context, Result};
 use daemonize_me::Daemon;
 use dirs::home_dir;
+use log::error;
 
 const STDOUT_FILE: &str = ".taskmasterd/taskmasterd.stdout";
 const STDERR_FILE: &str = ".taskmasterd/taskmasterd.stderr";
 
 fn hook(_ppid: i32, _cpid: i32) {
     if let Err(e) = main_loop() {
-        eprintln!("{}", e);
+        error!("{:#}", e);
     }
 }
 
@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use log::error;
+use nix::sys::epoll::{
+    epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::{SfdFlags, SignalFd};
+use nix::unistd::close;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Instant;
+
+/// Signals handled by the daemon, delivered through a signalfd instead of handlers
+const SIGNALS: [Signal; 5] = [
+    Signal::SIGCHLD,
+    Signal::SIGHUP,
+    Signal::SIGTERM,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+];
+
+const SIGNAL_TOKEN: u64 = 0;
+const LISTENER_TOKEN: u64 = 1;
+/// Client fds are registered with this offset added to them
+const CLIENT_TOKEN: u64 = 2;
+/// Output pipe fds are registered with this offset added to them
+const OUTPUT_TOKEN: u64 = 1 << 32;
+/// Pidfds of adopted processes are registered with this offset added to them
+const EXIT_TOKEN: u64 = 2 << 32;
+/// Notify sockets are registered with this offset added to them
+const NOTIFY_TOKEN: u64 = 3 << 32;
+/// Connections of health checks are registered with this offset added to them
+const PROBE_TOKEN: u64 = 4 << 32;
+
+/// Something the main loop has to react to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A signal was received
+    Signal(Signal),
+    /// A client is waiting on the control socket
+    Connection,
+    /// A connected client sent something or hung up
+    Client(RawFd),
+    /// A connected client can take more of the responses queued for it
+    Writable(RawFd),
+    /// A process wrote to an output pipe or closed it
+    Output(RawFd),
+    /// A process adopted from a previous daemon exited, the fd being its pidfd
+    Exit(RawFd),
+    /// A process sent a notification to its notify socket
+    Notify(RawFd),
+    /// The connection of a health check got established, writable or readable
+    Probe(RawFd),
+}
+
+/// Wait on the control socket and on signals with epoll, with an optional deadline
+pub struct EventLoop {
+    epoll: RawFd,
+    signals: SignalFd,
+}
+
+impl EventLoop {
+    pub fn new(listener: &impl AsRawFd) -> Result<Self> {
+        let mut mask = SigSet::empty();
+        SIGNALS.iter().for_each(|s| mask.add(*s));
+        mask.thread_block().context("Failed to block signals")?;
+        let signals = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)
+            .context("Failed to create signalfd")?;
+        let epoll =
+            epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC).context("Failed to create epoll")?;
+        let event_loop = Self { epoll, signals };
+        event_loop.register(event_loop.signals.as_raw_fd(), SIGNAL_TOKEN)?;
+        event_loop.register(listener.as_raw_fd(), LISTENER_TOKEN)?;
+        Ok(event_loop)
+    }
+
+    /// Watch a client connection, it is unwatched automatically once closed
+    pub fn watch(&self, fd: RawFd) -> Result<()> {
+        self.register(fd, CLIENT_TOKEN + fd as u64)
+    }
+
+    /// Watch an output pipe, it is unwatched automatically once closed
+    pub fn watch_output(&self, fd: RawFd) -> Result<()> {
+        self.register(fd, OUTPUT_TOKEN + fd as u64)
+    }
+
+    /// Watch the pidfd of an adopted process, it is unwatched automatically once closed
+    pub fn watch_exit(&self, fd: RawFd) -> Result<()> {
+        self.register(fd, EXIT_TOKEN + fd as u64)
+    }
+
+    /// Watch the notify socket of a process, it is unwatched automatically once closed
+    pub fn watch_notify(&self, fd: RawFd) -> Result<()> {
+        self.register(fd, NOTIFY_TOKEN + fd as u64)
+    }
+
+    /// Watch the connection of a health check, it is unwatched automatically once closed
+    ///
+    /// It is edge-triggered, as the connection stays writable once established.
+    pub fn watch_probe(&self, fd: RawFd) -> Result<()> {
+        let flags = EpollFlags::EPOLLIN | EpollFlags::EPOLLOUT | EpollFlags::EPOLLET;
+        let mut event = EpollEvent::new(flags, PROBE_TOKEN + fd as u64);
+        epoll_ctl(self.epoll, EpollOp::EpollCtlAdd, fd, &mut event)
+            .context("Failed to register fd in epoll")?;
+        Ok(())
+    }
+
+    /// Also wake up once a client connection is writable, or no longer
+    pub fn watch_writable(&self, fd: RawFd, writable: bool) -> Result<()> {
+        let flags = if writable {
+            EpollFlags::EPOLLIN | EpollFlags::EPOLLOUT
+        } else {
+            EpollFlags::EPOLLIN
+        };
+        let mut event = EpollEvent::new(flags, CLIENT_TOKEN + fd as u64);
+        epoll_ctl(self.epoll, EpollOp::EpollCtlMod, fd, &mut event)
+            .context("Failed to modify fd in epoll")?;
+        Ok(())
+    }
+
+    fn register(&self, fd: RawFd, token: u64) -> Result<()> {
+        let mut event = EpollEvent::new(EpollFlags::EPOLLIN, token);
+        epoll_ctl(self.epoll, EpollOp::EpollCtlAdd, fd, &mut event)
+            .context("Failed to register fd in epoll")?;
+        Ok(())
+    }
+
+    /// Block until something happens or `deadline` is reached
+    ///
+    /// An empty list means the deadline was reached, or that waiting failed, which is
+    /// logged.
+    pub fn wait(&mut self, deadline: Option<Instant>) -> Vec<Event> {
+        let timeout = match deadline {
+            // round up so we never wake up right before the deadline
+            Some(deadline) => deadline
+                .saturating_duration_since(Instant::now())
+                .as_micros()
+                .div_ceil(1000)
+                .min(isize::MAX as u128) as isize,
+            None => -1,
+        };
+        let mut ready = [EpollEvent::empty(); 16];
+        let n = match epoll_wait(self.epoll, &mut ready, timeout) {
+            Ok(n) => n,
+            Err(nix::errno::Errno::EINTR) => 0,
+            Err(e) => {
+                error!("Failed to wait for events: {}", e);
+                0
+            }
+        };
+        let mut events = Vec::new();
+        for event in &ready[..n] {
+            match event.data() {
+                SIGNAL_TOKEN => loop {
+                    let info = match self.signals.read_signal() {
+                        Ok(Some(info)) => info,
+                        Ok(None) => break,
+                        Err(e) => {
+                            error!("Failed to read signalfd: {}", e);
+                            break;
+                        }
+                    };
+                    let Ok(signal) = Signal::try_from(info.ssi_signo as i32) else {
+                        continue;
+                    };
+                    if !events.contains(&Event::Signal(signal)) {
+                        events.push(Event::Signal(signal));
+                    }
+                },
+                LISTENER_TOKEN => events.push(Event::Connection),
+                token if token >= PROBE_TOKEN => {
+                    events.push(Event::Probe((token - PROBE_TOKEN) as RawFd))
+                }
+                token if token >= NOTIFY_TOKEN => {
+                    events.push(Event::Notify((token - NOTIFY_TOKEN) as RawFd))
+                }
+                token if token >= EXIT_TOKEN => {
+                    events.push(Event::Exit((token - EXIT_TOKEN) as RawFd))
+                }
+                token if token >= OUTPUT_TOKEN => {
+                    events.push(Event::Output((token - OUTPUT_TOKEN) as RawFd))
+                }
+                token => {
+                    let fd = (token - CLIENT_TOKEN) as RawFd;
+                    let flags = event.events();
+                    if flags.contains(EpollFlags::EPOLLOUT) {
+                        events.push(Event::Writable(fd));
+                    }
+                    if flags.intersects(
+                        EpollFlags::EPOLLIN | EpollFlags::EPOLLHUP | EpollFlags::EPOLLERR,
+                    ) {
+                        events.push(Event::Client(fd));
+                    }
+                }
+            }
+        }
+        events
+    }
+}
+
+impl Drop for EventLoop {
+    fn drop(&mut self) {
+        let _ = close(self.epoll);
+    }
+}
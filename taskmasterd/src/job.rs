@@ -1,12 +1,22 @@
-use anyhow::{Ok, Result};
+use anyhow::{bail, Context, Ok, Result};
+use jobconfig::numprocs::NumProcs;
+use jobconfig::rolling::Rolling;
 use jobconfig::JobConfig;
+use log::{error, info, warn};
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
 use process::{Process, State};
+use rollout::Rollout;
 use serde::Deserialize;
-
+use std::collections::{BTreeMap, BTreeSet};
+use std::num::NonZeroU32;
 use std::path::PathBuf;
+use std::time::Instant;
+use taskmasterproto::{Health, Outcome, ProcessResult, ProcessState, ProgramStatus};
 
 pub mod jobconfig;
 pub mod process;
+pub mod rollout;
 
 const DEFAULT_CONFIG_PATHS: [&str; 3] =
     ["config.yml", "../config.yml", "/etc/taskmasterd/config.yml"];
@@ -17,6 +27,18 @@ pub struct Job {
     pub config: JobConfig,
     #[serde(skip)]
     pub processes: Vec<Process>,
+    #[serde(skip)]
+    pub rollout: Option<Rollout>,
+    /// Processes whose start waits for the dependencies of the program
+    #[serde(skip)]
+    pub queued_starts: BTreeSet<usize>,
+    /// Processes whose stop waits for the given processes of the programs depending on
+    /// this one to stop
+    #[serde(skip)]
+    pub queued_stops: BTreeMap<usize, BTreeSet<(String, usize)>>,
+    /// Group the program is a member of, kept until the program is replaced or removed
+    #[serde(skip)]
+    pub group: Option<String>,
 }
 
 impl PartialEq for Job {
@@ -29,117 +51,370 @@ impl Eq for Job {}
 
 impl Job {
     pub fn init(&mut self, name: &str) -> Result<()> {
-        for i in 0..self.config.numprocs.0.into() {
-            let process = Process::new(format!("{}-{}", name, i), &self.config);
-            self.processes.push(process);
+        if self.config.watchdog_sec.is_some() && !self.config.notify {
+            bail!("Invalid config for {}: watchdog_sec needs notify", name);
+        }
+        self.config
+            .credentials()
+            .with_context(|| format!("Invalid config for {}", name))?;
+        for _ in 0..self.config.numprocs.0.get() {
+            self.add_process(name);
         }
         Ok(())
     }
 
-    pub fn start(&mut self) {
-        for process in self.processes.iter_mut() {
-            process.start();
+    /// Append a process, named after its index so that indices stay stable
+    pub fn add_process(&mut self, name: &str) -> &mut Process {
+        let index = self.processes.len();
+        let process = Process::new(format!("{}-{}", name, index), &self.config);
+        self.processes.push(process);
+        self.processes.last_mut().expect("process was just pushed")
+    }
+
+    /// Whether `other` only differs by its number of processes, so it can be applied by
+    /// scaling instead of replacing the job
+    pub fn only_scales(&self, other: &Job) -> bool {
+        let config = JobConfig {
+            numprocs: self.config.numprocs,
+            ..other.config.clone()
+        };
+        config == self.config
+    }
+
+    /// Change the number of processes without touching the ones that are kept
+    ///
+    /// Processes past the new number are stopped, from the highest index, and removed by
+    /// `prune` once stopped. New processes are added stopped, it is up to the caller to start
+    /// them after their dependencies.
+    pub fn scale(&mut self, name: &str, numprocs: NumProcs) -> Vec<ProcessResult> {
+        let count = numprocs.0.get() as usize;
+        self.config.numprocs = numprocs;
+        let mut results = Vec::new();
+        for index in (count..self.processes.len()).rev() {
+            results.push(ProcessResult {
+                name: self.processes[index].name.clone(),
+                outcome: self.stop_process(index),
+            });
         }
+        for _ in self.processes.len()..count {
+            self.add_process(name);
+        }
+        self.prune();
+        results
     }
 
-    pub fn stop(&mut self) -> Result<()> {
-        for process in self.processes.iter_mut() {
-            process.stop(self.config.stopsignal, false)?;
+    /// Remove the processes left past `numprocs` by `scale` once they are stopped
+    pub fn prune(&mut self) {
+        let count = self.config.numprocs.0.get() as usize;
+        while self.processes.len() > count
+            && self
+                .processes
+                .last()
+                .is_some_and(|p| !(p.is_running() || p.has_group() || p.has_orphans()))
+        {
+            self.processes.pop();
         }
-        Ok(())
     }
 
-    pub fn restart(&mut self) -> Result<()> {
-        for process in self.processes.iter_mut() {
-            process.restart(&self.config)?;
+    pub fn stop(&mut self) {
+        self.rollout = None;
+        self.queued_starts.clear();
+        self.queued_stops.clear();
+        for index in 0..self.processes.len() {
+            if let Outcome::Error { reason } = self.stop_process(index) {
+                error!(process = self.processes[index].name.as_str(); "failed to stop: {}", reason);
+            }
         }
-        Ok(())
     }
 
+    pub fn start_process(&mut self, index: usize) -> Outcome {
+        self.queued_stops.remove(&index);
+        self.processes[index].start()
+    }
+
+    pub fn stop_process(&mut self, index: usize) -> Outcome {
+        self.queued_stops.remove(&index);
+        if self.queued_starts.remove(&index) {
+            return Outcome::Ok;
+        }
+        into_outcome(self.processes[index].stop(self.config.stopsignal, false))
+    }
+
+    /// Queue the process at `index` to start once the dependencies of the program meet
+    /// their condition
+    pub fn queue_start(&mut self, index: usize) -> Outcome {
+        self.queued_stops.remove(&index);
+        if self.processes[index].is_running() {
+            return Outcome::AlreadyRunning;
+        }
+        self.queued_starts.insert(index);
+        Outcome::Queued
+    }
+
+    /// Queue the process at `index` to stop once the `dependents` processes are stopped
+    pub fn queue_stop(&mut self, index: usize, dependents: BTreeSet<(String, usize)>) -> Outcome {
+        self.queued_starts.remove(&index);
+        self.queued_stops.insert(index, dependents);
+        Outcome::Queued
+    }
+
+    pub fn restart_process(&mut self, index: usize) -> Outcome {
+        into_outcome(self.processes[index].restart(&self.config))
+    }
+
+    /// Settings of a restart with the given options, `None` if it restarts all at once
+    pub fn rolling(
+        &self,
+        rolling: Option<bool>,
+        batch: Option<u32>,
+        pause: Option<u64>,
+    ) -> Option<Rolling> {
+        if !rolling.unwrap_or(self.config.rolling.is_some()) {
+            return None;
+        }
+        let mut settings = self.config.rolling.unwrap_or_default();
+        if let Some(batch) = batch.and_then(NonZeroU32::new) {
+            settings.batch = batch;
+        }
+        if let Some(pause) = pause {
+            settings.pause = pause;
+        }
+        Some(settings)
+    }
+
+    /// Queue the process at `index` for a rolling restart, carried out by `advance_rollout`
+    pub fn queue_restart(&mut self, index: usize, rolling: Rolling) -> Outcome {
+        self.rollout
+            .get_or_insert_with(|| Rollout::new(rolling))
+            .push(index);
+        Outcome::Queued
+    }
+
+    /// Restart the next batch of the rollout once the current one is running and the pause
+    /// after it is over
+    ///
+    /// The rollout is given up if a process of the batch goes FATAL or is stopped.
+    pub fn advance_rollout(&mut self, name: &str) {
+        let Some(rollout) = &mut self.rollout else {
+            return;
+        };
+        let now = Instant::now();
+        if !rollout.batch.is_empty() {
+            // processes removed by scaling down are not waited for
+            let batch = rollout.batch.iter().filter_map(|i| self.processes.get(*i));
+            let mut failed = None;
+            for process in batch {
+                let status = process.status();
+                match status.state {
+                    // with health checks, the process has to be healthy as well
+                    ProcessState::Running
+                        if matches!(status.health, Some(Health::Starting | Health::Unhealthy)) =>
+                    {
+                        return
+                    }
+                    ProcessState::Running => {}
+                    ProcessState::Starting | ProcessState::Stopping | ProcessState::Backoff => {
+                        return
+                    }
+                    _ => {
+                        failed = Some(status);
+                        break;
+                    }
+                }
+            }
+            if let Some(status) = failed {
+                error!(process = status.name.as_str(); "rolling restart given up: {}", status.description);
+                self.rollout = None;
+                return;
+            }
+            rollout.batch.clear();
+            rollout.resume_at = Some(now + rollout.pause);
+        }
+        if rollout.queue.is_empty() {
+            info!(program = name; "rolling restart done");
+            self.rollout = None;
+            return;
+        }
+        if rollout.resume_at.is_some_and(|resume_at| resume_at > now) {
+            return;
+        }
+        rollout.resume_at = None;
+        while rollout.batch.len() < rollout.size {
+            let Some(index) = rollout.queue.pop_front() else {
+                break;
+            };
+            let Some(process) = self.processes.get_mut(index) else {
+                continue;
+            };
+            info!(process = process.name.as_str(); "rolling restart");
+            if let Outcome::Error { reason } | Outcome::SpawnError { reason } =
+                into_outcome(process.restart(&self.config))
+            {
+                error!(process = process.name.as_str(); "failed to restart: {}", reason);
+            }
+            rollout.batch.push(index);
+        }
+    }
+
+    pub fn signal_process(&mut self, index: usize, signal: Signal) -> Outcome {
+        into_outcome(self.processes[index].signal(signal))
+    }
+
+    pub fn rotate_logs(&mut self, index: usize, reopen: bool) -> Outcome {
+        into_outcome(self.processes[index].rotate_logs(reopen))
+    }
+
+    /// Whether the program was started and not stopped since: one of its processes runs
+    /// without being asked to stop, or waits for its dependencies to start
+    pub fn is_up(&self) -> bool {
+        !self.queued_starts.is_empty()
+            || self
+                .processes
+                .iter()
+                .any(|p| p.is_running() && !p.is_stopping())
+    }
+
+    /// Whether a process is running, left members in its process group or has orphans
     pub fn is_running(&self) -> bool {
-        self.processes.iter().any(Process::is_running)
+        self.processes
+            .iter()
+            .any(|p| p.is_running() || p.has_group() || p.has_orphans())
     }
 
-    pub fn check_status(&mut self) -> Result<()> {
+    /// Handle the exit of one of the job's processes or of their orphans
+    ///
+    /// Returns `false` if `pid` does not belong to this job.
+    pub fn reap(&mut self, pid: Pid, code: Option<i32>) -> bool {
         use crate::job::jobconfig::autorestart::AutoRestart;
+        let process = match self.processes.iter_mut().find(|p| p.pid() == Some(pid)) {
+            Some(process) => process,
+            None => {
+                return self
+                    .processes
+                    .iter_mut()
+                    .any(|p| p.reap_probe(pid, code) || p.reap_orphan(pid))
+            }
+        };
+        process.exited(&self.config, code);
+        match &process.state {
+            State::Stopped(process::StoppedStatus::Unexpected) => {
+                // if autorestart is at true or unexpected, restart
+                if self.config.autorestart == AutoRestart::Always
+                    || self.config.autorestart == AutoRestart::Unexpected
+                {
+                    warn!(process = process.name.as_str(), transition = "UNEXPECTED"; "unexpected exit, restart");
+                    process.start();
+                } else {
+                    warn!(process = process.name.as_str(), transition = "UNEXPECTED"; "unexpected exit");
+                }
+            }
+            State::Stopped(process::StoppedStatus::Exited)
+                if self.config.autorestart == AutoRestart::Always =>
+            {
+                info!(process = process.name.as_str(); "exited, restart");
+                process.start();
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Handle the processes whose current state timed out
+    ///
+    /// Failures are logged for the process concerned, the other processes are still handled.
+    pub fn check_status(&mut self) {
+        let now = Instant::now();
+        // adopted processes are polled when pidfds are not supported
+        let gone = self
+            .processes
+            .iter()
+            .filter(|p| p.adopted_is_gone())
+            .filter_map(Process::pid)
+            .collect::<Vec<_>>();
+        for pid in gone {
+            self.reap(pid, None);
+        }
         for process in self.processes.iter_mut() {
-            process.update_status(&self.config)?;
+            process.check_group(now);
+            process.check_orphans(now);
+            process.check_health(now);
+            process.check_watchdog(now);
+            if !matches!(process.state_deadline(&self.config), Some(deadline) if deadline <= now) {
+                continue;
+            }
             match &mut process.state {
-                State::Stopped(status) => match status {
-                    process::StoppedStatus::Backoff { tries, started_at } => {
-                        if started_at.elapsed().as_secs() >= (*tries).into() {
-                            println!("{}: backoff expired, restart", process.name);
-                            process.start();
-                        }
-                    }
-                    process::StoppedStatus::Unexpected => {
-                        // if autorestart is at true or unexpected, restart
-                        if self.config.autorestart == AutoRestart::Always
-                            || self.config.autorestart == AutoRestart::Unexpected
-                        {
-                            println!("{}: unexpected exit, restart", process.name);
-                            process.start();
-                        } else {
-                            println!("{}: unexpected exit", process.name);
-                        }
-                    }
-                    process::StoppedStatus::Exited => {
-                        if self.config.autorestart == AutoRestart::Always {
-                            println!("{}: exited, restart", process.name);
+                State::Stopped(process::StoppedStatus::Backoff { .. }) => {
+                    info!(process = process.name.as_str(); "backoff expired, restart");
+                    process.start();
+                }
+                State::Running { status, .. } => match status {
+                    process::RunningStatus::StopRequested { restart, .. } => {
+                        warn!(process = process.name.as_str(); "stop timeout expired, kill");
+                        let restart = *restart;
+                        if kill(process) && restart {
                             process.start();
+                            info!(process = process.name.as_str(); "restarted after kill");
                         }
                     }
-                    process::StoppedStatus::Fatal => {}
-                    process::StoppedStatus::Stopped => {}
-                },
-                State::Running { status, .. } => match status {
-                    process::RunningStatus::StopRequested { since, restart } => {
-                        if since.elapsed().as_secs() >= self.config.stoptimeout.0 {
-                            println!("{}: stop timeout expired, kill", process.name);
-                            let restart = *restart;
-                            process.kill()?;
-                            if restart {
-                                process.start();
-                                println!("{}: restarted after kill", process.name);
-                            }
-                        }
+                    process::RunningStatus::StartRequested { .. } => {
+                        info!(process = process.name.as_str(), transition = "RUNNING"; "start period ended ({}s)", self.config.starttime.0);
+                        process.started();
                     }
-                    process::RunningStatus::StartRequested { start, .. } => {
-                        if start.elapsed().as_secs() >= self.config.starttime.0 {
-                            println!(
-                                "{}: start period ended ({}s)",
-                                process.name, self.config.starttime.0
-                            );
-                            *status = process::RunningStatus::Running;
-                        }
+                    // the process sent STOPPING=1 but did not exit in time
+                    process::RunningStatus::Running { .. } => {
+                        warn!(process = process.name.as_str(); "stop timeout expired, kill");
+                        kill(process);
                     }
-                    process::RunningStatus::Running => {}
                 },
+                State::Stopped(_) => {}
             }
         }
-        Ok(())
     }
 
-    pub fn print_status(&self) -> String {
-        let mut status = String::new();
-        if self.processes.is_empty() {
-            status.push_str("No process running\n");
-        } else {
-            self.processes.iter().for_each(|p| {
-                status.push_str(&p.to_string());
-            });
+    /// Earliest time at which one of the processes' state times out
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let rollout = self.rollout.as_ref().and_then(|rollout| rollout.resume_at);
+        self.processes
+            .iter()
+            .filter_map(|p| p.deadline(&self.config))
+            .chain(rollout)
+            .min()
+    }
+
+    /// Status of the processes at `indices`
+    pub fn status(&self, name: &str, indices: &[usize]) -> ProgramStatus {
+        ProgramStatus {
+            name: name.to_string(),
+            group: self.group.clone(),
+            processes: indices
+                .iter()
+                .map(|i| self.processes[*i].status())
+                .collect(),
         }
-        status
     }
 }
 
-impl Drop for Job {
-    fn drop(&mut self) {
-        assert!(
-            !self.is_running(),
-            "Job processes should be stopped before being dropped"
-        );
+/// Kill a process past its stop timeout, giving it another stop timeout if that fails
+///
+/// Returns whether the process was killed.
+fn kill(process: &mut Process) -> bool {
+    let result = process.kill();
+    killed(process, result)
+}
+
+fn killed(process: &mut Process, result: Result<()>) -> bool {
+    if let Err(e) = result {
+        error!(process = process.name.as_str(); "failed to kill, retrying after the stop timeout: {:#}", e);
+        process.postpone_kill();
+        return false;
     }
+    true
+}
+
+fn into_outcome(result: Result<Outcome>) -> Outcome {
+    result.unwrap_or_else(|e| Outcome::Error {
+        reason: format!("{:#}", e),
+    })
 }
 
 pub fn find_config() -> Option<PathBuf> {
@@ -186,6 +461,31 @@ mod tests {
         assert_eq!(jobs.programs, new_jobs.programs);
     }
 
+    #[test]
+    fn test_only_scales() {
+        let jobs: Jobs = serde_yaml::from_str(CONFIG_EXAMPLE).unwrap();
+        let mut scaled: Jobs = serde_yaml::from_str(CONFIG_EXAMPLE).unwrap();
+        let job = &jobs.programs["nginx"];
+        let scaled = scaled.programs.get_mut("nginx").unwrap();
+        scaled.config.numprocs = NumProcs(4.try_into().unwrap());
+        assert!(job.only_scales(scaled));
+        scaled.config.autostart = !job.config.autostart;
+        assert!(!job.only_scales(scaled));
+    }
+
+    #[test]
+    fn test_rolling() {
+        let mut jobs: Jobs = serde_yaml::from_str(CONFIG_EXAMPLE).unwrap();
+        let job = jobs.programs.get_mut("nginx").unwrap();
+        assert_eq!(job.rolling(None, Some(2), None), None);
+        let rolling = job.rolling(Some(true), None, Some(5)).unwrap();
+        assert_eq!((rolling.batch.get(), rolling.pause), (1, 5));
+        job.config.rolling = serde_yaml::from_str("batch: 3").unwrap();
+        let rolling = job.rolling(None, None, None).unwrap();
+        assert_eq!((rolling.batch.get(), rolling.pause), (3, 0));
+        assert_eq!(job.rolling(Some(false), None, None), None);
+    }
+
     #[test]
     fn test_find_config() {
         assert!(find_config().is_some());
@@ -203,4 +503,31 @@ mod tests {
         let jobs: Jobs = serde_yaml::from_str(CONFIG_EXAMPLE).unwrap();
         assert_eq!(jobs.programs.len(), 1);
     }
+
+    #[test]
+    fn test_failed_kill() {
+        use process::RunningStatus;
+        use std::time::Duration;
+
+        let mut jobs: Jobs =
+            serde_yaml::from_str("programs:\n  a:\n    cmd: sleep 1\n    stoptimeout: 1\n")
+                .unwrap();
+        jobs.init().unwrap();
+        let job = jobs.programs.get_mut("a").unwrap();
+        // never signaled, the failure is given to `killed`
+        let pid = Pid::from_raw(i32::MAX);
+        job.processes[0].state = State::Running {
+            pid,
+            status: RunningStatus::StopRequested {
+                since: Instant::now() - Duration::from_secs(10),
+                restart: true,
+            },
+        };
+        let error = nix::errno::Errno::EPERM.into();
+        assert!(!killed(&mut job.processes[0], Err(error)));
+        // the process is still supervised, and killed again after another stop timeout
+        assert_eq!(job.processes[0].pid(), Some(pid));
+        let deadline = job.next_deadline().unwrap();
+        assert!(deadline > Instant::now() + Duration::from_millis(500));
+    }
 }
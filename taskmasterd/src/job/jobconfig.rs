@@ -1,38 +1,78 @@
+use anyhow::{bail, Result};
 use autorestart::AutoRestart;
+use backups::Backups;
+use buffersize::BufferSize;
+use cmd::Cmd;
+use cpuaffinity::CpuAffinity;
+use dependson::DependsOn;
 use env::Env;
 use exitcodes::ExitCodes;
+use group::Group;
+use healthcheck::HealthCheck;
+use maxbytes::MaxBytes;
+use nice::Nice;
+use nix::unistd::{Gid, Uid};
 use numprocs::NumProcs;
+use oomscoreadj::OomScoreAdj;
+use priority::Priority;
+use rlimits::RLimits;
+use rolling::Rolling;
 use serde::Deserialize;
 use starttimeout::StartTimeout;
-use std::path::PathBuf;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
 use stopsignal::StopSignal;
 use stoptimeout::StopTimeout;
 use umask::Umask;
+use user::User;
 use workingdir::WorkingDir;
 
 pub mod autorestart;
+pub mod backups;
+pub mod buffersize;
+pub mod cmd;
+pub mod cpuaffinity;
+pub mod dependson;
 pub mod env;
 pub mod exitcodes;
+pub mod group;
+pub mod healthcheck;
+pub mod maxbytes;
+pub mod nice;
 pub mod numprocs;
+pub mod oomscoreadj;
+pub mod priority;
+pub mod rlimits;
+pub mod rolling;
 pub mod starttimeout;
 pub mod stopsignal;
 pub mod stoptimeout;
 pub mod umask;
+pub mod user;
 pub mod workingdir;
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 pub struct JobConfig {
-    pub cmd: String,
+    pub cmd: Cmd,
     #[serde(default)]
     pub numprocs: NumProcs,
     pub umask: Option<Umask>,
     #[serde(default)]
+    pub rlimits: RLimits,
+    pub nice: Option<Nice>,
+    pub oom_score_adj: Option<OomScoreAdj>,
+    pub cpu_affinity: Option<CpuAffinity>,
+    #[serde(default)]
     pub workingdir: WorkingDir,
     #[serde(default)]
     pub autostart: bool,
     #[serde(default)]
     pub autorestart: AutoRestart,
     #[serde(default)]
+    pub depends_on: DependsOn,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
     pub exitcodes: ExitCodes,
     #[serde(default)]
     pub startretries: u32,
@@ -42,7 +82,134 @@ pub struct JobConfig {
     pub starttime: StartTimeout,
     #[serde(default)]
     pub stopsignal: StopSignal,
+    pub rolling: Option<Rolling>,
+    pub healthcheck: Option<HealthCheck>,
+    #[serde(default)]
+    pub notify: bool,
+    pub watchdog_sec: Option<NonZeroU64>,
+    #[serde(default)]
+    pub stopasgroup: bool,
+    #[serde(default)]
+    pub killasgroup: bool,
     pub stdout: Option<PathBuf>,
+    #[serde(default)]
+    pub stdout_maxbytes: MaxBytes,
+    #[serde(default)]
+    pub stdout_backups: Backups,
+    #[serde(default)]
+    pub stdout_compress: bool,
+    #[serde(default)]
+    pub stdout_append: bool,
     pub stderr: Option<PathBuf>,
+    #[serde(default)]
+    pub stderr_maxbytes: MaxBytes,
+    #[serde(default)]
+    pub stderr_backups: Backups,
+    #[serde(default)]
+    pub stderr_compress: bool,
+    #[serde(default)]
+    pub stderr_append: bool,
+    #[serde(default)]
+    pub output_buffer: BufferSize,
+    #[serde(default)]
+    pub stdin: bool,
+    #[serde(default)]
+    pub tty: bool,
     pub env: Option<Env>,
+    pub user: Option<User>,
+    pub group: Option<Group>,
+    pub supplementary_groups: Option<Vec<Group>>,
+}
+
+/// Identity a process switches to before exec
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub uid: Uid,
+    pub gid: Gid,
+    pub groups: Vec<Gid>,
+}
+
+/// Log file an output stream of the processes is written to
+#[derive(Debug, Clone, Copy)]
+pub struct LogConfig<'a> {
+    pub path: &'a Path,
+    pub maxbytes: u64,
+    pub backups: u32,
+    pub compress: bool,
+    pub append: bool,
+}
+
+impl JobConfig {
+    pub fn stdout_log(&self) -> Option<LogConfig<'_>> {
+        Some(LogConfig {
+            path: self.stdout.as_deref()?,
+            maxbytes: self.stdout_maxbytes.0,
+            backups: self.stdout_backups.0,
+            compress: self.stdout_compress,
+            append: self.stdout_append,
+        })
+    }
+
+    pub fn stderr_log(&self) -> Option<LogConfig<'_>> {
+        Some(LogConfig {
+            path: self.stderr.as_deref()?,
+            maxbytes: self.stderr_maxbytes.0,
+            backups: self.stderr_backups.0,
+            compress: self.stderr_compress,
+            append: self.stderr_append,
+        })
+    }
+
+    /// Whether the final SIGKILL goes to the whole process group, implied by `stopasgroup`
+    pub fn killasgroup(&self) -> bool {
+        self.killasgroup || self.stopasgroup
+    }
+
+    /// Identity the processes should run as, if it differs from the daemon's
+    ///
+    /// Fails if the daemon does not have the privilege to switch to it.
+    pub fn credentials(&self) -> Result<Option<Credentials>> {
+        if self.user.is_none() && self.group.is_none() && self.supplementary_groups.is_none() {
+            return Ok(None);
+        }
+        let uid = self.user.as_ref().map_or_else(Uid::current, |u| u.uid);
+        let gid = match (&self.group, &self.user) {
+            (Some(group), _) => group.gid,
+            (None, Some(user)) => user.gid,
+            (None, None) => Gid::current(),
+        };
+        let groups = match (&self.supplementary_groups, &self.user) {
+            (Some(groups), _) => std::iter::once(gid)
+                .chain(groups.iter().map(|g| g.gid))
+                .collect(),
+            (None, Some(user)) => user.groups.clone(),
+            (None, None) => vec![gid],
+        };
+        if !Uid::effective().is_root() {
+            if uid == Uid::current() && gid == Gid::current() && self.supplementary_groups.is_none()
+            {
+                return Ok(None);
+            }
+            bail!(
+                "taskmasterd must run as root to switch to {}",
+                self.identity()
+            );
+        }
+        Ok(Some(Credentials { uid, gid, groups }))
+    }
+
+    fn identity(&self) -> String {
+        let mut identity = Vec::new();
+        if let Some(user) = &self.user {
+            identity.push(format!("user {}", user.name));
+        }
+        if let Some(group) = &self.group {
+            identity.push(format!("group {}", group.name));
+        }
+        if let Some(groups) = &self.supplementary_groups {
+            let names = groups.iter().map(|g| g.name.as_str()).collect::<Vec<_>>();
+            identity.push(format!("supplementary groups {}", names.join(", ")));
+        }
+        identity.join(", ")
+    }
 }
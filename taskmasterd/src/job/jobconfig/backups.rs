@@ -0,0 +1,12 @@
+use serde::Deserialize;
+
+/// Number of rotated log files kept, 0 truncates the log instead of rotating it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Backups(pub u32);
+
+/// Default is 10 backups
+impl Default for Backups {
+    fn default() -> Self {
+        Backups(10)
+    }
+}
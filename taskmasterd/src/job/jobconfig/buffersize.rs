@@ -0,0 +1,30 @@
+use super::maxbytes::parse_size;
+use serde::{Deserialize, Deserializer};
+use serde_yaml::Value;
+
+/// Size of the in-memory buffer of the recent output of each stream of a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferSize(pub usize);
+
+/// Default size is 64KB
+impl Default for BufferSize {
+    fn default() -> Self {
+        Self(64 * 1024)
+    }
+}
+
+impl<'de> Deserialize<'de> for BufferSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Value::deserialize(deserializer)? {
+            Value::Number(n) => n.as_u64(),
+            Value::String(s) => parse_size(&s),
+            _ => None,
+        }
+        .and_then(|size| usize::try_from(size).ok())
+        .map(Self)
+        .ok_or_else(|| serde::de::Error::custom("Expected a size like 1024, 10KB or 1MB"))
+    }
+}
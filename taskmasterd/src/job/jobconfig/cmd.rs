@@ -0,0 +1,116 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Deserializer};
+use serde_yaml::Value;
+use std::path::{Path, PathBuf};
+
+/// Command line of a program, split into the program and its arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cmd(pub Vec<String>);
+
+/// Deserialize the `Cmd` with serde and handle both a shell-like string and a sequence of strings
+impl<'de> Deserialize<'de> for Cmd {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let v = Value::deserialize(deserializer)?;
+        let argv = match v {
+            Value::String(s) => shell_words::split(&s)
+                .map_err(|e| serde::de::Error::custom(format!("Invalid cmd {:?}: {}", s, e)))?,
+            Value::Sequence(x) => x
+                .iter()
+                .map(|x| match x {
+                    Value::String(s) => Ok(s.to_string()),
+                    Value::Number(n) => Ok(n.to_string()),
+                    _ => Err(serde::de::Error::custom(
+                        "Expected a string or a number as argument in cmd",
+                    )),
+                })
+                .collect::<Result<Vec<String>, _>>()?,
+            _ => return Err(serde::de::Error::custom("Invalid cmd")),
+        };
+        if argv.is_empty() {
+            return Err(serde::de::Error::custom("cmd should not be empty"));
+        }
+        Ok(Self(argv))
+    }
+}
+
+impl Cmd {
+    #[inline]
+    pub fn program(&self) -> &str {
+        &self.0[0]
+    }
+
+    #[inline]
+    pub fn args(&self) -> &[String] {
+        &self.0[1..]
+    }
+
+    /// Find the executable to run, looking it up in `path` if it is a bare name
+    ///
+    /// A relative path is taken from `workingdir`, where the process runs. Symbolic links
+    /// are not followed, multi-call binaries tell what to do by the name they are run as.
+    pub fn resolve(&self, path: Option<&str>, workingdir: &Path) -> Result<PathBuf> {
+        let program = self.program();
+        if program.contains('/') {
+            let candidate = workingdir.join(program);
+            if !is_executable(&candidate) {
+                bail!("Failed to find command {}", candidate.display());
+            }
+            return Ok(candidate);
+        }
+        let path = path
+            .map(str::to_string)
+            .or_else(|| std::env::var("PATH").ok())
+            .unwrap_or_default();
+        std::env::split_paths(&path)
+            .map(|dir| dir.join(program))
+            .find(|candidate| is_executable(candidate))
+            .ok_or_else(|| anyhow!("Failed to find command {} in PATH", program))
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmd_string() {
+        let cmd: Cmd =
+            serde_yaml::from_str(r#""/usr/local/bin/nginx -c '/etc/nginx/my test.conf'""#).unwrap();
+        assert_eq!(cmd.program(), "/usr/local/bin/nginx");
+        assert_eq!(cmd.args(), ["-c", "/etc/nginx/my test.conf"]);
+    }
+
+    #[test]
+    fn test_cmd_list() {
+        let cmd: Cmd = serde_yaml::from_str("[echo, \"hello world\", 42]").unwrap();
+        assert_eq!(cmd.0, ["echo", "hello world", "42"]);
+    }
+
+    #[test]
+    fn test_cmd_invalid() {
+        assert!(serde_yaml::from_str::<Cmd>(r#""echo 'unterminated""#).is_err());
+        assert!(serde_yaml::from_str::<Cmd>(r#""""#).is_err());
+    }
+
+    #[test]
+    fn test_cmd_resolve() {
+        let cmd: Cmd = serde_yaml::from_str("sh -c true").unwrap();
+        assert!(cmd.resolve(None, Path::new("/")).unwrap().is_absolute());
+        let cmd: Cmd = serde_yaml::from_str("./sh").unwrap();
+        assert_eq!(
+            cmd.resolve(None, Path::new("/bin")).unwrap(),
+            Path::new("/bin/./sh")
+        );
+        assert!(cmd.resolve(None, Path::new("/nonexistent")).is_err());
+    }
+}
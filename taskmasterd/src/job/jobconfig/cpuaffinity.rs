@@ -0,0 +1,47 @@
+use nix::sched::CpuSet;
+use serde::{Deserialize, Deserializer};
+use serde_yaml::Value;
+
+/// CPUs the processes are allowed to run on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuAffinity(pub Vec<usize>);
+
+impl CpuAffinity {
+    pub fn cpu_set(&self) -> CpuSet {
+        let mut set = CpuSet::new();
+        for cpu in &self.0 {
+            set.set(*cpu)
+                .expect("CPU indices are checked when deserializing");
+        }
+        set
+    }
+}
+
+/// Deserialize a single CPU index or a list of them
+impl<'de> Deserialize<'de> for CpuAffinity {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let cpus = match Value::deserialize(deserializer)? {
+            Value::Sequence(cpus) => cpus,
+            cpu @ Value::Number(_) => vec![cpu],
+            _ => return Err(serde::de::Error::custom("Expected a CPU or a list of CPUs")),
+        };
+        if cpus.is_empty() {
+            return Err(serde::de::Error::custom("The list of CPUs is empty"));
+        }
+        cpus.iter()
+            .map(|cpu| match cpu.as_u64() {
+                Some(cpu) if (cpu as usize) < CpuSet::count() => Ok(cpu as usize),
+                Some(cpu) => Err(serde::de::Error::custom(format!(
+                    "Invalid CPU {}, expected an index below {}",
+                    cpu,
+                    CpuSet::count()
+                ))),
+                None => Err(serde::de::Error::custom("Invalid CPU")),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Deserializer};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+
+/// Programs that have to meet a condition before the processes of the program start
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DependsOn(pub BTreeMap<String, Condition>);
+
+/// State a dependency has to reach, for all its processes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Condition {
+    /// Spawned, or running
+    Started,
+    /// Running past its start time, or exited successfully after that
+    #[default]
+    Running,
+    /// Running with its health checks succeeding
+    Healthy,
+}
+
+/// Deserialize a list of program names, which have to be running, or a map of program names
+/// to conditions
+impl<'de> Deserialize<'de> for DependsOn {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Value::deserialize(deserializer)? {
+            Value::Sequence(names) => names
+                .into_iter()
+                .map(|name| match name {
+                    Value::String(name) => Ok((name, Condition::default())),
+                    _ => Err(serde::de::Error::custom("Expected a program name")),
+                })
+                .collect::<Result<_, _>>()
+                .map(Self),
+            map @ Value::Mapping(_) => BTreeMap::deserialize(map)
+                .map(Self)
+                .map_err(serde::de::Error::custom),
+            _ => Err(serde::de::Error::custom(
+                "Expected a list of programs or a map of programs to conditions",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depends_on() {
+        let depends_on: DependsOn = serde_yaml::from_str("[db, cache]").unwrap();
+        assert_eq!(depends_on.0["db"], Condition::Running);
+        assert_eq!(depends_on.0.len(), 2);
+        let depends_on: DependsOn = serde_yaml::from_str("{db: healthy, cache: started}").unwrap();
+        assert_eq!(depends_on.0["db"], Condition::Healthy);
+        assert_eq!(depends_on.0["cache"], Condition::Started);
+        assert!(serde_yaml::from_str::<DependsOn>("{db: ready}").is_err());
+        assert!(serde_yaml::from_str::<DependsOn>("db").is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Deserializer};
+use serde_yaml::Value;
+
+/// Group to run the processes as, given by name or gid and resolved when the config is loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub name: String,
+    pub gid: nix::unistd::Gid,
+}
+
+impl<'de> Deserialize<'de> for Group {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let v = Value::deserialize(deserializer)?;
+        let group = match &v {
+            Value::String(name) => nix::unistd::Group::from_name(name),
+            Value::Number(gid) => {
+                let gid = gid
+                    .as_u64()
+                    .and_then(|gid| u32::try_from(gid).ok())
+                    .ok_or_else(|| serde::de::Error::custom("Invalid gid"))?;
+                nix::unistd::Group::from_gid(nix::unistd::Gid::from_raw(gid))
+            }
+            _ => {
+                return Err(serde::de::Error::custom(
+                    "Expected a name or a gid as group",
+                ))
+            }
+        }
+        .map_err(serde::de::Error::custom)?
+        .ok_or_else(|| match &v {
+            Value::String(name) => serde::de::Error::custom(format!("Unknown group {}", name)),
+            _ => serde::de::Error::custom(format!(
+                "Unknown group id {}",
+                v.as_u64().unwrap_or_default()
+            )),
+        })?;
+        Ok(Self {
+            name: group.name,
+            gid: group.gid,
+        })
+    }
+}
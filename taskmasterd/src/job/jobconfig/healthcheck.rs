@@ -0,0 +1,100 @@
+use super::cmd::Cmd;
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr};
+use std::num::{NonZeroU32, NonZeroU64};
+use std::path::PathBuf;
+
+/// Check run periodically on the running processes, which are restarted after too many
+/// consecutive failures
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct HealthCheck {
+    #[serde(flatten)]
+    pub check: Check,
+    /// Seconds between the end of a check and the start of the next one
+    #[serde(default = "default_interval")]
+    pub interval: NonZeroU64,
+    /// Seconds after which a check that did not complete fails
+    #[serde(default = "default_timeout")]
+    pub timeout: NonZeroU64,
+    /// Number of consecutive failures after which the process is restarted
+    #[serde(default = "default_retries")]
+    pub retries: NonZeroU32,
+    /// Seconds after the process started during which failures are not counted
+    #[serde(default)]
+    pub start_period: u64,
+}
+
+/// What a health check does, it succeeds if...
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Check {
+    /// ...the command exits with 0, it runs with the same user, environment and working
+    /// directory as the process
+    Exec { cmd: Cmd },
+    /// ...a TCP connection can be established
+    Tcp {
+        #[serde(default = "default_host")]
+        host: IpAddr,
+        port: u16,
+    },
+    /// ...a GET request is answered with a 2xx or 3xx status
+    Http {
+        #[serde(default = "default_host")]
+        host: IpAddr,
+        port: u16,
+        #[serde(default = "default_path")]
+        path: String,
+    },
+    /// ...a connection to the Unix socket can be established
+    Unix { path: PathBuf },
+}
+
+/// Default interval is 10 seconds
+fn default_interval() -> NonZeroU64 {
+    NonZeroU64::new(10).expect("10 should not be 0")
+}
+
+/// Default timeout is 5 seconds
+fn default_timeout() -> NonZeroU64 {
+    NonZeroU64::new(5).expect("5 should not be 0")
+}
+
+/// Default is to restart after 3 failures
+fn default_retries() -> NonZeroU32 {
+    NonZeroU32::new(3).expect("3 should not be 0")
+}
+
+fn default_host() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_healthcheck() {
+        let healthcheck: HealthCheck =
+            serde_yaml::from_str("type: http\nport: 8080\npath: /health\nretries: 2").unwrap();
+        assert_eq!(
+            healthcheck.check,
+            Check::Http {
+                host: default_host(),
+                port: 8080,
+                path: "/health".to_string()
+            }
+        );
+        assert_eq!(healthcheck.retries.get(), 2);
+        assert_eq!(healthcheck.interval.get(), 10);
+        let healthcheck: HealthCheck =
+            serde_yaml::from_str("type: exec\ncmd: test -e /tmp/ok").unwrap();
+        assert!(matches!(healthcheck.check, Check::Exec { .. }));
+        assert!(
+            serde_yaml::from_str::<HealthCheck>("type: tcp\nhost: localhost\nport: 1").is_err()
+        );
+    }
+}
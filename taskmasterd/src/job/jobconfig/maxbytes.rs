@@ -0,0 +1,59 @@
+use serde::{Deserialize, Deserializer};
+use serde_yaml::Value;
+
+/// Size at which a log file is rotated, 0 disables the rotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxBytes(pub u64);
+
+/// Default size is 50MB
+impl Default for MaxBytes {
+    fn default() -> Self {
+        Self(50 * 1024 * 1024)
+    }
+}
+
+/// Parse a size in bytes, with an optional `KB`, `MB` or `GB` suffix
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let unit = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" | "K" => 1024,
+        "MB" | "M" => 1024 * 1024,
+        "GB" | "G" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+impl<'de> Deserialize<'de> for MaxBytes {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Value::deserialize(deserializer)? {
+            Value::Number(n) => n.as_u64().map(Self),
+            Value::String(s) => parse_size(&s).map(Self),
+            _ => None,
+        }
+        .ok_or_else(|| serde::de::Error::custom("Expected a size like 1024, 10KB or 50MB"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("10KB"), Some(10 * 1024));
+        assert_eq!(parse_size("50 mb"), Some(50 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_size("10TB"), None);
+        assert_eq!(parse_size("MB"), None);
+    }
+}
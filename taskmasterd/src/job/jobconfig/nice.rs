@@ -0,0 +1,21 @@
+use serde::{Deserialize, Deserializer};
+
+/// Scheduling priority of the processes, from -20 (highest) to 19 (lowest)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nice(pub i32);
+
+impl<'de> Deserialize<'de> for Nice {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let nice = i32::deserialize(deserializer)?;
+        if !(-20..=19).contains(&nice) {
+            return Err(serde::de::Error::custom(format!(
+                "Invalid nice value {}, expected a value between -20 and 19",
+                nice
+            )));
+        }
+        Ok(Self(nice))
+    }
+}
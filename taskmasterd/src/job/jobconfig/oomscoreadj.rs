@@ -0,0 +1,21 @@
+use serde::{Deserialize, Deserializer};
+
+/// Adjustment of the OOM killer score of the processes, from -1000 (never killed) to 1000
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OomScoreAdj(pub i32);
+
+impl<'de> Deserialize<'de> for OomScoreAdj {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let adj = i32::deserialize(deserializer)?;
+        if !(-1000..=1000).contains(&adj) {
+            return Err(serde::de::Error::custom(format!(
+                "Invalid oom_score_adj {}, expected a value between -1000 and 1000",
+                adj
+            )));
+        }
+        Ok(Self(adj))
+    }
+}
@@ -0,0 +1,12 @@
+use serde::Deserialize;
+
+/// Order of the program relative to the others, lower starts first and stops last
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub struct Priority(pub i32);
+
+/// Default priority is 999, like supervisor
+impl Default for Priority {
+    fn default() -> Self {
+        Priority(999)
+    }
+}
@@ -0,0 +1,129 @@
+use nix::libc::rlim_t;
+use nix::sys::resource::{Resource, RLIM_INFINITY};
+use serde::{Deserialize, Deserializer};
+use serde_yaml::Value;
+
+/// Resource limits applied to the processes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RLimits {
+    /// Maximum number of open files
+    pub nofile: Option<RLimit>,
+    /// Maximum number of processes of the user
+    pub nproc: Option<RLimit>,
+    /// Maximum size of a core dump, in bytes
+    pub core: Option<RLimit>,
+    /// Maximum size of the address space, in bytes
+    #[serde(rename = "as")]
+    pub address_space: Option<RLimit>,
+}
+
+/// Soft and hard values of a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RLimit {
+    pub soft: rlim_t,
+    pub hard: rlim_t,
+}
+
+impl RLimits {
+    /// The limits to set, with the resource they apply to
+    pub fn resources(&self) -> Vec<(Resource, RLimit)> {
+        [
+            (Resource::RLIMIT_NOFILE, self.nofile),
+            (Resource::RLIMIT_NPROC, self.nproc),
+            (Resource::RLIMIT_CORE, self.core),
+            (Resource::RLIMIT_AS, self.address_space),
+        ]
+        .into_iter()
+        .filter_map(|(resource, limit)| Some((resource, limit?)))
+        .collect()
+    }
+}
+
+fn limit_value<E: serde::de::Error>(v: &Value) -> Result<rlim_t, E> {
+    match v {
+        Value::String(s) if s == "unlimited" => Ok(RLIM_INFINITY),
+        Value::Number(n) => n
+            .as_u64()
+            .map(|n| n as rlim_t)
+            .ok_or_else(|| E::custom("Invalid limit")),
+        _ => Err(E::custom("Expected a number or unlimited as limit")),
+    }
+}
+
+/// Deserialize a single value used as both soft and hard limit, or a `soft`/`hard` map
+impl<'de> Deserialize<'de> for RLimit {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let v = Value::deserialize(deserializer)?;
+        let (soft, hard) = match &v {
+            Value::Mapping(map) => {
+                if let Some(key) = map
+                    .keys()
+                    .find(|key| !matches!(key.as_str(), Some("soft" | "hard")))
+                {
+                    return Err(serde::de::Error::custom(format!(
+                        "Unknown limit field {:?}, expected soft or hard",
+                        key.as_str().unwrap_or_default()
+                    )));
+                }
+                let hard = map.get("hard").map(limit_value).transpose()?;
+                let soft = map.get("soft").map(limit_value).transpose()?;
+                match (soft, hard) {
+                    (Some(soft), Some(hard)) => (soft, hard),
+                    (Some(limit), None) | (None, Some(limit)) => (limit, limit),
+                    (None, None) => {
+                        return Err(serde::de::Error::custom("Expected a soft or hard limit"))
+                    }
+                }
+            }
+            v => {
+                let limit = limit_value(v)?;
+                (limit, limit)
+            }
+        };
+        if soft > hard {
+            return Err(serde::de::Error::custom(
+                "The soft limit cannot be above the hard limit",
+            ));
+        }
+        Ok(Self { soft, hard })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rlimits() {
+        let limits: RLimits =
+            serde_yaml::from_str("{nofile: 1024, core: unlimited, as: {soft: 10, hard: 20}}")
+                .unwrap();
+        assert_eq!(
+            limits.resources(),
+            vec![
+                (
+                    Resource::RLIMIT_NOFILE,
+                    RLimit {
+                        soft: 1024,
+                        hard: 1024
+                    }
+                ),
+                (
+                    Resource::RLIMIT_CORE,
+                    RLimit {
+                        soft: RLIM_INFINITY,
+                        hard: RLIM_INFINITY
+                    }
+                ),
+                (Resource::RLIMIT_AS, RLimit { soft: 10, hard: 20 }),
+            ]
+        );
+        assert!(serde_yaml::from_str::<RLimits>("{nofile: {soft: 20, hard: 10}}").is_err());
+        assert!(serde_yaml::from_str::<RLimits>("{nofile: {max: 10}}").is_err());
+        assert!(serde_yaml::from_str::<RLimits>("{stack: 10}").is_err());
+    }
+}
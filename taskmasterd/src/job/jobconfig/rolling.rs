@@ -0,0 +1,29 @@
+use serde::Deserialize;
+use std::num::NonZeroU32;
+
+/// Restarts of the program are rolling by default, restarting its processes in batches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rolling {
+    /// Number of processes restarted at once
+    #[serde(default = "default_batch")]
+    pub batch: NonZeroU32,
+    /// Seconds to wait once a batch is running before restarting the next one
+    #[serde(default)]
+    pub pause: u64,
+}
+
+/// Rolling restarts of a single process at a time, without pause
+impl Default for Rolling {
+    fn default() -> Self {
+        Self {
+            batch: default_batch(),
+            pause: 0,
+        }
+    }
+}
+
+/// Default batch is a single process
+fn default_batch() -> NonZeroU32 {
+    NonZeroU32::new(1).expect("1 should not be 0")
+}
@@ -10,6 +10,12 @@ impl<'de> Deserialize<'de> for Umask {
     {
         let s = String::deserialize(deserializer)?;
         let umask = u32::from_str_radix(&s, 8).map_err(serde::de::Error::custom)?;
+        if umask > 0o777 {
+            return Err(serde::de::Error::custom(format!(
+                "Invalid umask {}, expected at most 777",
+                s
+            )));
+        }
         Ok(Self(umask))
     }
 }
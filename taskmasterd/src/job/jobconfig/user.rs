@@ -0,0 +1,68 @@
+use serde::{Deserialize, Deserializer};
+use serde_yaml::Value;
+use std::ffi::CString;
+use std::path::PathBuf;
+
+/// User to run the processes as, given by name or uid and resolved when the config is loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub name: String,
+    pub uid: nix::unistd::Uid,
+    /// Primary group of the user
+    pub gid: nix::unistd::Gid,
+    pub home: PathBuf,
+    /// Groups the user is a member of, including its primary group
+    pub groups: Vec<nix::unistd::Gid>,
+}
+
+impl<'de> Deserialize<'de> for User {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let v = Value::deserialize(deserializer)?;
+        let user = match &v {
+            Value::String(name) => nix::unistd::User::from_name(name),
+            Value::Number(uid) => {
+                let uid = uid
+                    .as_u64()
+                    .and_then(|uid| u32::try_from(uid).ok())
+                    .ok_or_else(|| serde::de::Error::custom("Invalid uid"))?;
+                nix::unistd::User::from_uid(nix::unistd::Uid::from_raw(uid))
+            }
+            _ => return Err(serde::de::Error::custom("Expected a name or a uid as user")),
+        }
+        .map_err(serde::de::Error::custom)?
+        .ok_or_else(|| match &v {
+            Value::String(name) => serde::de::Error::custom(format!("Unknown user {}", name)),
+            _ => serde::de::Error::custom(format!(
+                "Unknown user id {}",
+                v.as_u64().unwrap_or_default()
+            )),
+        })?;
+        let c_name = CString::new(user.name.as_str()).map_err(serde::de::Error::custom)?;
+        let groups =
+            nix::unistd::getgrouplist(&c_name, user.gid).map_err(serde::de::Error::custom)?;
+        Ok(Self {
+            name: user.name,
+            uid: user.uid,
+            gid: user.gid,
+            home: user.dir,
+            groups,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user() {
+        let by_name: User = serde_yaml::from_str("root").unwrap();
+        let by_uid: User = serde_yaml::from_str("0").unwrap();
+        assert_eq!(by_name, by_uid);
+        assert!(by_name.uid.is_root());
+        assert!(serde_yaml::from_str::<User>("no-such-user-hopefully").is_err());
+    }
+}
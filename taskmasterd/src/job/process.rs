@@ -1,19 +1,59 @@
+use crate::job::jobconfig::cmd::Cmd;
 use crate::job::jobconfig::stopsignal::StopSignal;
 use crate::job::jobconfig::JobConfig;
-use anyhow::{Context, Result};
-use nix::sys::signal::Signal;
-use nix::sys::stat::{umask, Mode};
+use crate::procfs::{self, ProcStat};
+use crate::statefile::{SavedProcess, SavedStatus};
+use crate::upgrade::{HandedState, ProcessHandoff};
+use anyhow::{bail, Context, Result};
+use dirs::home_dir;
+use health::Health;
+use log::{debug, error, info, warn};
+use logfile::LogFile;
+use nix::errno::Errno;
+use nix::sys::signal::{kill, killpg, Signal};
 use nix::unistd::Pid;
+use notify::{Notification, NotifySocket};
+use output::{make_fifo, open_fifo, open_fifo_end, open_pty, set_nonblocking, Pipe, Stream};
+use ringbuffer::RingBuffer;
+use setup::ChildSetup;
 use std::fmt::{Debug, Display, Formatter};
-use std::process::{Child, Command};
-use std::time::Instant;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use taskmasterproto::{Health as HealthState, Outcome, ProcessState, ProcessStatus};
+
+pub mod health;
+pub mod logfile;
+mod notify;
+pub mod output;
+mod ringbuffer;
+mod setup;
+
+/// How often a lingering process group is checked for remaining members
+const GROUP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often an adopted process is checked for when pidfds are not supported
+const ADOPTED_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 // TODO Restrain PID to Running states
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug)]
 pub enum RunningStatus {
-    Running,
-    StartRequested { start: Instant, tries: u32 },
-    StopRequested { since: Instant, restart: bool },
+    /// Running past its start time, with the state of its health checks if it has some
+    Running {
+        health: Option<Health>,
+    },
+    StartRequested {
+        start: Instant,
+        tries: u32,
+    },
+    StopRequested {
+        since: Instant,
+        restart: bool,
+    },
 }
 
 /// Status of a stopped process
@@ -40,17 +80,22 @@ pub enum StoppedStatus {
 #[derive(Debug)]
 pub enum State {
     Stopped(StoppedStatus),
-    Running {
-        pid: Pid,
-        child: Child,
-        status: RunningStatus,
-    },
+    Running { pid: Pid, status: RunningStatus },
 }
 
 impl Display for RunningStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            RunningStatus::Running => write!(f, "RUNNING"),
+            RunningStatus::Running { health: None } => write!(f, "RUNNING"),
+            RunningStatus::Running {
+                health: Some(health),
+            } => match health.state {
+                HealthState::Starting => write!(f, "RUNNING (health: starting)"),
+                HealthState::Healthy => write!(f, "RUNNING (HEALTHY)"),
+                HealthState::Unhealthy => {
+                    write!(f, "RUNNING (UNHEALTHY, failures: {})", health.failures)
+                }
+            },
             RunningStatus::StartRequested { start, tries } => write!(
                 f,
                 "START_REQUESTED (tries: {}, since: {})",
@@ -89,29 +134,62 @@ impl Display for StoppedStatus {
     }
 }
 
-impl Display for State {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let width = 10;
-        match self {
-            State::Stopped(fatal) => write!(f, "pid: {:>width$} - {}", "N/A", fatal),
-            State::Running { pid, status, .. } => {
-                write!(f, "pid: {:>width$} - {}", pid, status)
-            }
-        }
-    }
-}
-
 impl Default for State {
     fn default() -> Self {
         Self::Stopped(StoppedStatus::default())
     }
 }
 
+/// Process group left behind by a process stopped with `killasgroup`
+#[derive(Debug)]
+struct LingeringGroup {
+    pgid: Pid,
+    /// Time at which the members still alive get killed, `None` once they were
+    kill_at: Option<Instant>,
+}
+
+/// Process started by a previous daemon, which is not a child of this one
+#[derive(Debug)]
+struct Adopted {
+    /// Readable once the process exits, `None` if the kernel does not support pidfds
+    pidfd: Option<OwnedFd>,
+    /// Whether the main loop already watches the pidfd
+    registered: bool,
+}
+
 #[derive(Debug)]
 pub struct Process {
     pub name: String,
     pub state: State,
     config: JobConfig,
+    /// Start time of the current process in clock ticks since boot, 0 if unknown
+    starttime: u64,
+    spawned_at: SystemTime,
+    adopted: Option<Adopted>,
+    group: Option<LingeringGroup>,
+    /// Sessions started by the process that may still have members
+    sessions: Vec<Pid>,
+    /// Descendants reparented to the daemon after their parent exited
+    orphans: Vec<Pid>,
+    /// Time at which the orphans get killed, once they were asked to stop
+    orphans_kill_at: Option<Instant>,
+    /// Pipes the process writes its output to, kept until they are closed
+    outputs: Vec<Pipe>,
+    /// Pipe or pseudo-terminal the process reads its input from, if configured
+    stdin: Option<File>,
+    stdout_log: Option<LogFile>,
+    stderr_log: Option<LogFile>,
+    /// Recent output, kept across restarts
+    stdout_buffer: RingBuffer,
+    stderr_buffer: RingBuffer,
+    /// Socket the process sends its notifications to if configured, kept across restarts
+    notify: Option<NotifySocket>,
+    /// Status last sent by the process with `STATUS=`
+    notice: Option<String>,
+    /// Time at which the process announced with `STOPPING=1` that it is shutting down
+    stopping: Option<Instant>,
+    /// Time at which the process gets restarted, unless it sends `WATCHDOG=1` before
+    watchdog_at: Option<Instant>,
 }
 
 impl Process {
@@ -120,6 +198,23 @@ impl Process {
             name,
             state: State::default(),
             config: config.clone(),
+            starttime: 0,
+            spawned_at: UNIX_EPOCH,
+            adopted: None,
+            group: None,
+            sessions: Vec::new(),
+            orphans: Vec::new(),
+            orphans_kill_at: None,
+            outputs: Vec::new(),
+            stdin: None,
+            stdout_log: None,
+            stderr_log: None,
+            stdout_buffer: RingBuffer::new(config.output_buffer.0),
+            stderr_buffer: RingBuffer::new(config.output_buffer.0),
+            notify: None,
+            notice: None,
+            stopping: None,
+            watchdog_at: None,
         }
     }
 
@@ -135,32 +230,86 @@ impl Process {
     }
 
     fn try_start(&mut self) -> Result<()> {
-        let mut command = Command::new(
-            std::fs::canonicalize(&self.config.cmd).context("Failed to find command")?,
-        );
-        command.current_dir(self.config.workingdir.0.clone());
-        if let Some(env) = &self.config.env {
-            command.envs(env.0.iter());
+        let mut command = command(&self.config.cmd, &self.config)?;
+        if self.config.notify {
+            command.env("NOTIFY_SOCKET", self.notify_socket()?);
+            if let Some(watchdog) = self.config.watchdog_sec {
+                command.env("WATCHDOG_USEC", (watchdog.get() * 1_000_000).to_string());
+            }
+        }
+        // the logs are opened once, and kept across restarts
+        if let Some(log) = self
+            .config
+            .stdout_log()
+            .filter(|_| self.stdout_log.is_none())
+        {
+            self.stdout_log = Some(LogFile::open(&log)?);
         }
-        if let Some(stdout) = &self.config.stdout {
-            command.stdout(std::fs::File::create(stdout).context("Failed to open stdout file")?);
+        if let Some(log) = self
+            .config
+            .stderr_log()
+            .filter(|_| self.stderr_log.is_none())
+        {
+            self.stderr_log = Some(LogFile::open(&log)?);
         }
-        if let Some(stderr) = &self.config.stderr {
-            command.stderr(std::fs::File::create(stderr).context("Failed to open stderr file")?);
+        let mut pty = None;
+        let mut stdin = None;
+        if self.config.tty {
+            // the terminal output is merged into stdout
+            let (master, slave) = open_pty()?;
+            command.stdin(slave.try_clone()?);
+            command.stdout(slave.try_clone()?);
+            command.stderr(slave);
+            pty = Some(master);
+        } else {
+            if self.config.stdin {
+                let path = self.fifo("stdin")?;
+                command.stdin(open_fifo(&path)?);
+                stdin = Some(open_fifo_end(&path, true)?);
+            }
+            for stream in [Stream::Stdout, Stream::Stderr] {
+                let path = self.fifo(stream.name())?;
+                let fifo = open_fifo(&path)?;
+                // a pipe left open by the previous run is read from until it is closed
+                if !self.outputs.iter().any(|pipe| pipe.reads(&path)) {
+                    let pipe = open_fifo_end(&path, false)?;
+                    self.outputs.push(Pipe::new(pipe.into(), stream)?);
+                }
+                match stream {
+                    Stream::Stdout => command.stdout(fifo),
+                    Stream::Stderr => command.stderr(fifo),
+                };
+            }
         }
 
-        if let Some(umask_value) = &self.config.umask {
-            umask(Mode::from_bits_truncate(umask_value.0));
+        let setup = ChildSetup::new(&self.config)?;
+        // SAFETY: only async-signal-safe calls are made between fork and exec
+        unsafe {
+            command.pre_exec(move || Ok(setup.apply()?));
         }
 
         let child = command.spawn()?;
-        // FIXME Needs to be configurable
-        umask(Mode::from_bits_truncate(0o022));
+        let pid = Pid::from_raw(child.id() as i32);
+        self.stdin = match pty {
+            Some(master) => {
+                let stdin = master.try_clone()?;
+                set_nonblocking(&stdin).context("Failed to set stdin pipe to non-blocking")?;
+                self.outputs.push(Pipe::new(master.into(), Stream::Stdout)?);
+                Some(stdin)
+            }
+            None => stdin,
+        };
+        self.sessions.push(pid);
+        self.starttime = procfs::stat(pid).map_or(0, |stat| stat.starttime);
+        self.spawned_at = SystemTime::now();
+        self.notice = None;
+        self.stopping = None;
+        self.watchdog_at = None;
+        info!(process = self.name.as_str(), pid = pid.as_raw(), transition = "STARTING"; "spawned");
 
         let tries = self.get_tries();
         self.state = State::Running {
-            pid: Pid::from_raw(child.id() as i32),
-            child,
+            pid,
             status: RunningStatus::StartRequested {
                 start: Instant::now(),
                 tries,
@@ -173,123 +322,1023 @@ impl Process {
     fn get_stopped_status(&self) -> StoppedStatus {
         let tries = self.get_tries();
         if tries < self.config.startretries {
-            println!("{}: backing off", self.name);
+            info!(process = self.name.as_str(), transition = "BACKOFF"; "backing off");
             StoppedStatus::Backoff {
                 tries: tries + 1,
                 started_at: Instant::now(),
             }
         } else {
-            println!("{}: giving up", self.name);
+            error!(process = self.name.as_str(), transition = "FATAL"; "giving up");
             StoppedStatus::Fatal
         }
     }
 
-    pub fn start(&mut self) {
+    pub fn start(&mut self) -> Outcome {
         if let State::Stopped(_) = self.state {
             if let Err(e) = self.try_start() {
-                eprintln!("{}: failed to start: {}", self.name, e);
+                error!(process = self.name.as_str(); "failed to start: {:#}", e);
                 self.state = State::Stopped(self.get_stopped_status());
+                return Outcome::SpawnError {
+                    reason: format!("{:#}", e),
+                };
             };
+            Outcome::Ok
         } else {
-            eprintln!("{}: already running", self.name);
+            debug!(process = self.name.as_str(); "already running");
+            Outcome::AlreadyRunning
+        }
+    }
+
+    /// Mark a process that is not running as FATAL, when it cannot be started
+    pub fn give_up(&mut self) {
+        if let State::Stopped(_) = self.state {
+            self.state = State::Stopped(StoppedStatus::Fatal);
         }
     }
 
-    pub fn stop(&mut self, stop_signal: StopSignal, restart: bool) -> Result<()> {
+    pub fn stop(&mut self, stop_signal: StopSignal, restart: bool) -> Result<Outcome> {
+        let signal = Signal::from(stop_signal);
+        let orphans = self.stop_orphans(signal);
         if let State::Running { pid, status, .. } = &mut self.state {
-            nix::sys::signal::kill(*pid, Signal::from(stop_signal))?;
+            if self.config.stopasgroup || (signal == Signal::SIGKILL && self.config.killasgroup()) {
+                killpg(*pid, signal)?;
+            } else {
+                kill(*pid, signal)?;
+            }
             *status = RunningStatus::StopRequested {
                 since: Instant::now(),
                 restart,
             };
         } else if let State::Stopped(StoppedStatus::Backoff { .. }) = &self.state {
             self.state = State::Stopped(StoppedStatus::Stopped);
+        } else if !orphans {
+            return Ok(Outcome::NotRunning);
+        }
+        Ok(Outcome::Ok)
+    }
+
+    /// Send `signal` to the orphans, they get killed if still alive after the stop timeout
+    ///
+    /// Returns `false` if there is no orphan.
+    fn stop_orphans(&mut self, signal: Signal) -> bool {
+        if self.orphans.is_empty() {
+            return false;
+        }
+        for pid in &self.orphans {
+            if let Err(e) = kill(*pid, signal) {
+                warn!(process = self.name.as_str(), orphan = pid.as_raw(); "failed to signal orphan: {}", e);
+            }
+        }
+        self.orphans_kill_at =
+            Some(Instant::now() + Duration::from_secs(self.config.stoptimeout.0));
+        true
+    }
+
+    /// Whether a process that is not one of the leaders belongs to one of the sessions or
+    /// process groups started by this process
+    pub fn owns(&self, stat: &ProcStat) -> bool {
+        self.sessions
+            .iter()
+            .any(|session| *session == stat.sid || *session == stat.pgid)
+    }
+
+    /// Forget the sessions that have no member left
+    ///
+    /// Sessions are kept while there are orphans, which may have left their process group.
+    pub fn prune_sessions(&mut self) {
+        if !self.orphans.is_empty() {
+            return;
+        }
+        let pid = self.pid();
+        self.sessions.retain(|session| {
+            Some(*session) == pid || !matches!(killpg(*session, None), Err(Errno::ESRCH))
+        });
+    }
+
+    /// Take charge of a descendant that was reparented to the daemon
+    pub fn adopt(&mut self, pid: Pid) {
+        if self.orphans.contains(&pid) {
+            return;
+        }
+        info!(process = self.name.as_str(), orphan = pid.as_raw(); "adopted orphan");
+        self.orphans.push(pid);
+        // orphans adopted while the others are stopping are stopped too
+        if self.orphans_kill_at.is_some() {
+            if let Err(e) = kill(pid, Signal::from(self.config.stopsignal)) {
+                warn!(process = self.name.as_str(), orphan = pid.as_raw(); "failed to signal orphan: {}", e);
+            }
+        }
+    }
+
+    /// Forget an orphan once reaped
+    ///
+    /// Returns `false` if `pid` is not one of the orphans.
+    pub fn reap_orphan(&mut self, pid: Pid) -> bool {
+        let len = self.orphans.len();
+        self.orphans.retain(|orphan| *orphan != pid);
+        if self.orphans.len() == len {
+            return false;
+        }
+        info!(process = self.name.as_str(), orphan = pid.as_raw(); "orphan exited");
+        if self.orphans.is_empty() {
+            self.orphans_kill_at = None;
+        }
+        true
+    }
+
+    pub fn has_orphans(&self) -> bool {
+        !self.orphans.is_empty()
+    }
+
+    /// Kill the orphans still alive once their stop timeout expires
+    pub fn check_orphans(&mut self, now: Instant) {
+        if self.orphans_kill_at.is_some_and(|kill_at| kill_at <= now) {
+            warn!(process = self.name.as_str(); "stop timeout expired, kill orphans");
+            for pid in &self.orphans {
+                if let Err(e) = kill(*pid, Signal::SIGKILL) {
+                    warn!(process = self.name.as_str(), orphan = pid.as_raw(); "failed to kill orphan: {}", e);
+                }
+            }
+            self.orphans_kill_at = None;
+        }
+    }
+
+    /// Send a signal to the process if it is running
+    pub fn signal(&self, signal: Signal) -> Result<Outcome> {
+        if let State::Running { pid, .. } = &self.state {
+            kill(*pid, signal)?;
+            Ok(Outcome::Ok)
+        } else {
+            Ok(Outcome::NotRunning)
         }
-        Ok(())
     }
 
     /// Kill the process
     /// This is a shortcut for `stop(StopSignal::Kill)`
     pub fn kill(&mut self) -> Result<()> {
-        self.stop(StopSignal::Kill, false)?;
+        let pid = self.pid();
+        match self.stop(StopSignal::Kill, false) {
+            Err(e) if e.downcast_ref::<Errno>() != Some(&Errno::ESRCH) => return Err(e),
+            // a process that is already gone is stopped
+            _ => {}
+        }
         self.state = State::Stopped(StoppedStatus::Stopped);
+        if let Some(pgid) = pid.filter(|_| self.config.killasgroup()) {
+            self.group = Some(LingeringGroup {
+                pgid,
+                kill_at: None,
+            });
+        }
         // not sure if that's the right way to do it
         Ok(())
     }
 
-    pub fn restart(&mut self, config: &JobConfig) -> Result<()> {
+    /// Start the stop timeout over, for a process that could not be killed once it expired
+    pub fn postpone_kill(&mut self) {
+        let now = Instant::now();
+        if let State::Running {
+            status: RunningStatus::StopRequested { since, .. },
+            ..
+        } = &mut self.state
+        {
+            *since = now;
+        }
+        if self.stopping.is_some() {
+            self.stopping = Some(now);
+        }
+    }
+
+    /// Whether the process group of a process stopped as a group still has members
+    pub fn has_group(&self) -> bool {
+        self.group.is_some()
+    }
+
+    /// Forget the lingering process group once empty, kill it once its stop timeout expires
+    pub fn check_group(&mut self, now: Instant) {
+        let group = match &mut self.group {
+            Some(group) => group,
+            None => return,
+        };
+        if let Err(Errno::ESRCH) = killpg(group.pgid, None) {
+            debug!(process = self.name.as_str(), pgid = group.pgid.as_raw(); "process group is empty");
+            self.group = None;
+        } else if group.kill_at.is_some_and(|kill_at| kill_at <= now) {
+            warn!(process = self.name.as_str(), pgid = group.pgid.as_raw(); "stop timeout expired, kill process group");
+            if let Err(e) = killpg(group.pgid, Signal::SIGKILL) {
+                warn!(process = self.name.as_str(), pgid = group.pgid.as_raw(); "failed to kill process group: {}", e);
+            }
+            group.kill_at = None;
+        }
+    }
+
+    pub fn restart(&mut self, config: &JobConfig) -> Result<Outcome> {
         match &self.state {
-            State::Running { .. } => self.stop(config.stopsignal, true)?,
+            State::Running { .. } => self.stop(config.stopsignal, true),
             State::Stopped(status) => {
                 if let StoppedStatus::Backoff { .. } = status {
                     self.state = State::Stopped(StoppedStatus::Stopped);
                 }
-                self.start();
+                Ok(self.start())
             }
         }
-        Ok(())
     }
 
-    /// Update the process state
-    pub fn update_status(&mut self, config: &JobConfig) -> Result<()> {
-        if let State::Running { child, status, .. } = &mut self.state {
-            if let Some(exit_status) = child.try_wait()? {
-                let expected = if let Some(exit_code) = exit_status.code() {
-                    config.exitcodes.is_valid(exit_code)
-                } else {
-                    true // <== process received a signal, so it exiting is expected
-                };
-                self.state = State::Stopped(match status {
-                    RunningStatus::StartRequested { tries, .. } => {
-                        println!(
-                            "{}: exited before being fully started ({} tries)",
-                            self.name, tries
-                        );
-                        self.get_stopped_status()
-                    }
-                    RunningStatus::StopRequested { restart, .. } => {
-                        if *restart {
-                            self.state = State::Stopped(StoppedStatus::Stopped);
-                            println!("{}: exited and will be restarted", self.name);
-                            self.start();
-                            return Ok(()); // sale
-                        } else {
-                            println!("{}: has been stopped", self.name);
-                            StoppedStatus::Stopped
-                        }
+    /// Update the process state after it was reaped with the given exit code
+    ///
+    /// `code` is `None` when the process was killed by a signal.
+    pub fn exited(&mut self, config: &JobConfig, code: Option<i32>) {
+        if let State::Running {
+            pid,
+            status: RunningStatus::StopRequested { since, .. },
+        } = &self.state
+        {
+            // the other members of the group are waited for, and killed with the process
+            if config.killasgroup() && killpg(*pid, None).is_ok() {
+                self.group = Some(LingeringGroup {
+                    pgid: *pid,
+                    kill_at: Some(*since + Duration::from_secs(config.stoptimeout.0)),
+                });
+            }
+        }
+        // the exit status of a process that is not a child is unknown
+        let adopted = self.adopted.take().is_some();
+        if let State::Running { status, .. } = &self.state {
+            let expected = if adopted {
+                false
+            } else if let Some(exit_code) = code {
+                config.exitcodes.is_valid(exit_code)
+            } else {
+                true // <== process received a signal, so it exiting is expected
+            };
+            self.state = State::Stopped(match status {
+                RunningStatus::StartRequested { tries, .. } => {
+                    warn!(process = self.name.as_str(), tries = *tries; "exited before being fully started");
+                    self.get_stopped_status()
+                }
+                RunningStatus::StopRequested { restart, .. } => {
+                    if *restart {
+                        self.state = State::Stopped(StoppedStatus::Stopped);
+                        info!(process = self.name.as_str(); "exited and will be restarted");
+                        self.start();
+                        return; // sale
+                    } else {
+                        info!(process = self.name.as_str(), transition = "STOPPED"; "has been stopped");
+                        StoppedStatus::Stopped
                     }
-                    RunningStatus::Running => {
-                        if expected {
-                            println!("{}: exited", self.name);
-                            StoppedStatus::Exited
-                        } else {
-                            StoppedStatus::Unexpected
-                        }
+                }
+                RunningStatus::Running { .. } => {
+                    if expected {
+                        info!(process = self.name.as_str(), transition = "EXITED", code = code; "exited");
+                        StoppedStatus::Exited
+                    } else {
+                        StoppedStatus::Unexpected
                     }
-                });
+                }
+            });
+        }
+    }
+
+    /// Health checks of the process if it has some, for a process started at `start`
+    fn health(&self, start: Instant) -> Option<Health> {
+        let healthcheck = self.config.healthcheck.as_ref()?;
+        Some(Health::new(healthcheck, start))
+    }
+
+    /// The process ran past its start time or notified it is ready, its health checks and
+    /// watchdog start
+    pub fn started(&mut self) {
+        if let State::Running { status, .. } = &mut self.state {
+            if let RunningStatus::StartRequested { start, .. } = status {
+                let health = self.config.healthcheck.as_ref();
+                *status = RunningStatus::Running {
+                    health: health.map(|healthcheck| Health::new(healthcheck, *start)),
+                };
+                self.arm_watchdog();
+            }
+        }
+    }
+
+    /// Run the health checks that are due, restarting the process after too many failures
+    pub fn check_health(&mut self, now: Instant) {
+        let result = match &mut self.state {
+            State::Running {
+                status:
+                    RunningStatus::Running {
+                        health: Some(health),
+                    },
+                ..
+            } => health.poll(&self.config, now),
+            _ => None,
+        };
+        if let Some(success) = result {
+            self.health_result(success);
+        }
+    }
+
+    /// Take the result of an exec health check
+    ///
+    /// Returns `false` if `pid` is not the command of a health check of this process.
+    pub fn reap_probe(&mut self, pid: Pid, code: Option<i32>) -> bool {
+        let result = match &mut self.state {
+            State::Running {
+                status:
+                    RunningStatus::Running {
+                        health: Some(health),
+                    },
+                ..
+            } => health.reap(&self.config, pid, code),
+            _ => None,
+        };
+        if let Some(success) = result {
+            self.health_result(success);
+        }
+        result.is_some()
+    }
+
+    fn health_result(&mut self, success: bool) {
+        let State::Running {
+            status: RunningStatus::Running {
+                health: Some(health),
+            },
+            ..
+        } = &mut self.state
+        else {
+            return;
+        };
+        if health.record(&self.name, &self.config, success) {
+            warn!(process = self.name.as_str(); "unhealthy, restart");
+            if let Err(e) = self.stop(self.config.stopsignal, true) {
+                error!(process = self.name.as_str(); "failed to restart: {:#}", e);
+            }
+        }
+    }
+
+    /// Address of the notify socket given to the process, the socket is bound on first use
+    ///
+    /// The name is derived from the files directory of the daemon, so that a daemon
+    /// restarted after a crash binds the same one for the processes it adopts.
+    fn notify_socket(&mut self) -> Result<String> {
+        let dir = home_dir()
+            .context("could not find home directory")?
+            .join(crate::FILES_DIR);
+        let metadata =
+            fs::metadata(&dir).with_context(|| format!("Failed to stat {}", dir.display()))?;
+        let name = format!(
+            "taskmasterd/{:x}-{:x}/{}",
+            metadata.dev(),
+            metadata.ino(),
+            self.name
+        );
+        if self.notify.is_none() {
+            self.notify = Some(NotifySocket::bind(&name)?);
+        }
+        Ok(format!("@{}", name))
+    }
+
+    /// Named pipe the process uses for `stream`, created on first use
+    ///
+    /// Unlike anonymous pipes, they outlive the daemon: a daemon restarted after a crash
+    /// reopens them for the processes it adopts.
+    fn fifo(&self, stream: &str) -> Result<PathBuf> {
+        let dir = home_dir()
+            .context("could not find home directory")?
+            .join(crate::FILES_DIR)
+            .join("pipes");
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(format!("{}.{}", self.name, stream));
+        make_fifo(&path)?;
+        Ok(path)
+    }
+
+    /// Reopen the pipes of a process adopted from a previous daemon
+    fn reopen_pipes(&mut self) -> Result<()> {
+        if self.config.stdin {
+            self.stdin = Some(open_fifo_end(&self.fifo("stdin")?, true)?);
+        }
+        for stream in [Stream::Stdout, Stream::Stderr] {
+            let pipe = open_fifo_end(&self.fifo(stream.name())?, false)?;
+            self.outputs.push(Pipe::new(pipe.into(), stream)?);
+        }
+        self.reopen_logs()
+    }
+
+    /// Open the logs a previous daemon wrote to, without truncating them
+    fn reopen_logs(&mut self) -> Result<()> {
+        if let Some(mut log) = self.config.stdout_log() {
+            log.append = true;
+            self.stdout_log = Some(LogFile::open(&log)?);
+        }
+        if let Some(mut log) = self.config.stderr_log() {
+            log.append = true;
+            self.stderr_log = Some(LogFile::open(&log)?);
+        }
+        Ok(())
+    }
+
+    /// Handle the notifications the process sent to its notify socket
+    ///
+    /// Only the process and the members of its sessions and process groups are listened to.
+    pub fn read_notifications(&mut self) {
+        let received = match &self.notify {
+            Some(socket) => socket.receive(),
+            None => return,
+        };
+        for (sender, notifications) in received {
+            if Some(sender) != self.pid() && !procfs::stat(sender).is_some_and(|s| self.owns(&s)) {
+                debug!(process = self.name.as_str(), sender = sender.as_raw(); "ignored notification from another process");
+                continue;
+            }
+            for notification in notifications {
+                self.notified(notification);
+            }
+        }
+    }
+
+    fn notified(&mut self, notification: Notification) {
+        let status = match &mut self.state {
+            State::Running { status, .. } => status,
+            State::Stopped(_) => return,
+        };
+        match notification {
+            Notification::Ready => {
+                if let RunningStatus::StartRequested { .. } = status {
+                    info!(process = self.name.as_str(), transition = "RUNNING"; "ready");
+                    self.started();
+                }
+            }
+            Notification::Status(notice) => {
+                self.notice = Some(notice).filter(|notice| !notice.is_empty());
             }
+            Notification::Stopping => {
+                if self.stopping.is_none() && self.set_stopping() {
+                    info!(process = self.name.as_str(); "stopping on its own");
+                }
+            }
+            Notification::Watchdog => self.arm_watchdog(),
+            Notification::WatchdogTrigger => {
+                if self.watchdog_deadline().is_some() {
+                    self.watchdog_expired();
+                }
+            }
+        }
+    }
+
+    /// Give a running process the stop timeout to exit, without health checks nor watchdog
+    ///
+    /// Returns `false` if the process is not running past its start.
+    fn set_stopping(&mut self) -> bool {
+        let State::Running {
+            status: RunningStatus::Running { health },
+            ..
+        } = &mut self.state
+        else {
+            return false;
+        };
+        *health = None;
+        self.stopping = Some(Instant::now());
+        self.watchdog_at = None;
+        true
+    }
+
+    /// Start the watchdog over, if the process is running with one and is not stopping
+    fn arm_watchdog(&mut self) {
+        let running = matches!(
+            self.state,
+            State::Running {
+                status: RunningStatus::Running { .. },
+                ..
+            }
+        );
+        if let Some(watchdog) = self.config.watchdog_sec.filter(|_| running) {
+            if self.stopping.is_none() {
+                self.watchdog_at = Some(Instant::now() + Duration::from_secs(watchdog.get()));
+            }
+        }
+    }
+
+    /// Time at which the process gets restarted by its watchdog
+    fn watchdog_deadline(&self) -> Option<Instant> {
+        match &self.state {
+            State::Running {
+                status: RunningStatus::Running { .. },
+                ..
+            } => self.watchdog_at,
+            _ => None,
+        }
+    }
+
+    /// Restart the process if it did not send `WATCHDOG=1` in time
+    pub fn check_watchdog(&mut self, now: Instant) {
+        if self
+            .watchdog_deadline()
+            .is_some_and(|deadline| deadline <= now)
+        {
+            self.watchdog_expired();
+        }
+    }
+
+    fn watchdog_expired(&mut self) {
+        warn!(process = self.name.as_str(); "watchdog expired, restart");
+        self.watchdog_at = None;
+        if let Err(e) = self.stop(self.config.stopsignal, true) {
+            error!(process = self.name.as_str(); "failed to restart: {:#}", e);
+        }
+    }
+
+    /// Connection of the running health check, if the main loop does not watch it yet
+    pub fn unregistered_probe(&mut self) -> Option<RawFd> {
+        match &mut self.state {
+            State::Running {
+                status:
+                    RunningStatus::Running {
+                        health: Some(health),
+                    },
+                ..
+            } => health.unregistered_probe(),
+            _ => None,
+        }
+    }
+
+    /// Whether `fd` is the connection of the running health check
+    pub fn has_probe(&self, fd: RawFd) -> bool {
+        match &self.state {
+            State::Running {
+                status:
+                    RunningStatus::Running {
+                        health: Some(health),
+                    },
+                ..
+            } => health.has_probe(fd),
+            _ => false,
+        }
+    }
+
+    /// Notify socket of the process, if the main loop does not watch it yet
+    pub fn unregistered_notify(&mut self) -> Option<RawFd> {
+        let socket = self.notify.as_mut().filter(|socket| !socket.registered)?;
+        socket.registered = true;
+        Some(socket.as_raw_fd())
+    }
+
+    /// Whether `fd` is the notify socket of the process
+    pub fn has_notify(&self, fd: RawFd) -> bool {
+        self.notify
+            .as_ref()
+            .is_some_and(|socket| socket.as_raw_fd() == fd)
+    }
+
+    /// Time at which something has to be checked, the state or the lingering process group
+    pub fn deadline(&self, config: &JobConfig) -> Option<Instant> {
+        // nothing tells when a process group gets empty, so it is polled
+        let group = self.group.as_ref().map(|group| {
+            let poll = Instant::now() + GROUP_POLL_INTERVAL;
+            group.kill_at.map_or(poll, |kill_at| kill_at.min(poll))
+        });
+        let adopted = self
+            .adopted
+            .as_ref()
+            .filter(|adopted| adopted.pidfd.is_none())
+            .map(|_| Instant::now() + ADOPTED_POLL_INTERVAL);
+        let health = match &self.state {
+            State::Running {
+                status:
+                    RunningStatus::Running {
+                        health: Some(health),
+                    },
+                ..
+            } => Some(health.deadline()),
+            _ => None,
+        };
+        self.state_deadline(config)
+            .into_iter()
+            .chain(health)
+            .chain(self.watchdog_deadline())
+            .chain(group)
+            .chain(adopted)
+            .chain(self.orphans_kill_at)
+            .min()
+    }
+
+    /// Time at which the current state times out, if it does
+    pub fn state_deadline(&self, config: &JobConfig) -> Option<Instant> {
+        match &self.state {
+            State::Running { status, .. } => match status {
+                // with notify, the process is running once it sends READY=1
+                RunningStatus::StartRequested { .. } if config.notify => None,
+                RunningStatus::StartRequested { start, .. } => {
+                    Some(*start + Duration::from_secs(config.starttime.0))
+                }
+                RunningStatus::StopRequested { since, .. } => {
+                    Some(*since + Duration::from_secs(config.stoptimeout.0))
+                }
+                // a process that sent STOPPING=1 is given the stop timeout to exit
+                RunningStatus::Running { .. } => self
+                    .stopping
+                    .map(|since| since + Duration::from_secs(config.stoptimeout.0)),
+            },
+            State::Stopped(StoppedStatus::Backoff { tries, started_at }) => {
+                Some(*started_at + Duration::from_secs((*tries).into()))
+            }
+            State::Stopped(_) => None,
+        }
+    }
+
+    pub fn pid(&self) -> Option<Pid> {
+        match &self.state {
+            State::Running { pid, .. } => Some(*pid),
+            State::Stopped(_) => None,
+        }
+    }
+
+    pub fn status(&self) -> ProcessStatus {
+        let (state, description) = match (&self.state, self.stopping) {
+            (
+                State::Running {
+                    status: RunningStatus::Running { .. },
+                    ..
+                },
+                Some(since),
+            ) => (
+                ProcessState::Stopping,
+                format!("STOPPING (since: {})", since.elapsed().as_secs()),
+            ),
+            (State::Stopped(status), _) => (
+                match status {
+                    StoppedStatus::Backoff { .. } => ProcessState::Backoff,
+                    StoppedStatus::Fatal => ProcessState::Fatal,
+                    StoppedStatus::Unexpected => ProcessState::Unexpected,
+                    StoppedStatus::Exited => ProcessState::Exited,
+                    StoppedStatus::Stopped => ProcessState::Stopped,
+                },
+                status.to_string(),
+            ),
+            (State::Running { status, .. }, _) => (
+                match status {
+                    RunningStatus::StartRequested { .. } => ProcessState::Starting,
+                    RunningStatus::Running { .. } => ProcessState::Running,
+                    RunningStatus::StopRequested { .. } => ProcessState::Stopping,
+                },
+                status.to_string(),
+            ),
+        };
+        let health = match &self.state {
+            State::Running {
+                status:
+                    RunningStatus::Running {
+                        health: Some(health),
+                    },
+                ..
+            } => Some(health.state),
+            _ => None,
+        };
+        ProcessStatus {
+            name: self.name.clone(),
+            pid: self.pid().map(Pid::as_raw),
+            orphans: self.orphans.iter().map(|pid| pid.as_raw()).collect(),
+            state,
+            health,
+            description,
+            notice: self.notice.clone().filter(|_| self.is_running()),
+        }
+    }
+
+    /// Pipes that are not watched by the main loop yet
+    pub fn unregistered_outputs(&mut self) -> impl Iterator<Item = &mut Pipe> {
+        self.outputs.iter_mut().filter(|pipe| !pipe.registered)
+    }
+
+    /// Read what the process wrote to the pipe `fd`, log it and keep it in the buffer of
+    /// its stream
+    ///
+    /// Returns the stream and the data read, or `None` once the pipe is closed or if it is
+    /// not one of the process's.
+    pub fn read_output(&mut self, fd: RawFd) -> Option<(Stream, Vec<u8>)> {
+        let index = self
+            .outputs
+            .iter()
+            .position(|pipe| pipe.as_raw_fd() == fd)?;
+        // a single read per event, so that a chatty process does not starve the others
+        let mut buffer = [0; 64 * 1024];
+        let pipe = &mut self.outputs[index];
+        let stream = pipe.stream;
+        let data = match pipe.read(&mut buffer) {
+            Ok(0) => {
+                self.outputs.remove(index);
+                return None;
+            }
+            Ok(n) => &buffer[..n],
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {
+                return Some((stream, Vec::new()))
+            }
+            // the master side of a pseudo-terminal fails once the process is gone
+            Err(e) if e.raw_os_error() == Some(Errno::EIO as i32) => {
+                self.outputs.remove(index);
+                return None;
+            }
+            Err(e) => {
+                error!(process = self.name.as_str(); "failed to read output: {}", e);
+                self.outputs.remove(index);
+                return None;
+            }
+        };
+        let (log, recent) = match stream {
+            Stream::Stdout => (&mut self.stdout_log, &mut self.stdout_buffer),
+            Stream::Stderr => (&mut self.stderr_log, &mut self.stderr_buffer),
+        };
+        recent.push(data);
+        if let Some(log) = log {
+            if let Err(e) = log.write(data) {
+                error!(process = self.name.as_str(); "{:#}", e);
+            }
+        }
+        Some((stream, data.to_vec()))
+    }
+
+    /// Whether input can be written to the process
+    pub fn has_stdin(&self) -> bool {
+        self.config.stdin || self.config.tty
+    }
+
+    pub fn has_tty(&self) -> bool {
+        self.config.tty
+    }
+
+    /// Write `data` to the stdin of the process
+    pub fn write_input(&mut self, data: &[u8]) -> Result<()> {
+        let stdin = match (&self.state, &mut self.stdin) {
+            (State::Running { .. }, Some(stdin)) => stdin,
+            (State::Running { .. }, None) => bail!("{} does not keep its stdin open", self.name),
+            (State::Stopped(_), _) => bail!("{} is not running", self.name),
+        };
+        match stdin.write_all(data) {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                bail!("The stdin of {} is full, input dropped", self.name)
+            }
+            result => result.with_context(|| format!("Failed to write to {}", self.name)),
+        }
+    }
+
+    /// The last `lines` lines of recent output of `stream`
+    pub fn tail(&self, stream: Stream, lines: usize) -> Vec<u8> {
+        match stream {
+            Stream::Stdout => self.stdout_buffer.tail(lines),
+            Stream::Stderr => self.stderr_buffer.tail(lines),
+        }
+    }
+
+    /// Rotate the log files, or only reopen them if `reopen` is set
+    pub fn rotate_logs(&mut self, reopen: bool) -> Result<Outcome> {
+        for log in [&mut self.stdout_log, &mut self.stderr_log]
+            .into_iter()
+            .flatten()
+        {
+            if reopen {
+                log.reopen()?;
+            } else {
+                log.rotate()?;
+            }
+        }
+        Ok(Outcome::Ok)
+    }
+
+    /// State to persist for a later daemon to adopt the process, `None` if it is not running
+    pub fn saved(&self) -> Option<SavedProcess> {
+        let (pid, status) = match &self.state {
+            State::Running { pid, status } => (pid, status),
+            State::Stopped(_) => return None,
+        };
+        Some(SavedProcess {
+            pid: pid.as_raw(),
+            starttime: self.starttime,
+            spawned_at: self
+                .spawned_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            status: match status {
+                RunningStatus::StartRequested { tries, .. } => {
+                    SavedStatus::Starting { tries: *tries }
+                }
+                RunningStatus::Running { .. } => SavedStatus::Running,
+                RunningStatus::StopRequested { restart, .. } => {
+                    SavedStatus::Stopping { restart: *restart }
+                }
+            },
+        })
+    }
+
+    /// Take back a process started by a previous daemon, unless it is gone or its pid now
+    /// belongs to another process
+    pub fn resume(&mut self, saved: &SavedProcess) {
+        let pid = Pid::from_raw(saved.pid);
+        let pidfd = match procfs::pidfd_open(pid) {
+            Ok(pidfd) => Some(pidfd),
+            Err(Errno::ENOSYS) => None,
+            Err(_) => return,
+        };
+        // checked after opening the pidfd, so that it refers to the same process
+        if saved.starttime == 0
+            || procfs::stat(pid).map(|stat| stat.starttime) != Some(saved.starttime)
+        {
+            return;
+        }
+        info!(process = self.name.as_str(), pid = saved.pid; "adopted from a previous daemon");
+        self.restore_running(saved);
+        if self.config.notify {
+            if let Err(e) = self.notify_socket() {
+                warn!(process = self.name.as_str(); "{:#}", e);
+            }
+        }
+        self.adopted = Some(Adopted {
+            pidfd,
+            registered: false,
+        });
+        self.sessions.push(pid);
+        if self.config.tty {
+            // its terminal was closed with the previous daemon
+            info!(process = self.name.as_str(); "lost its terminal, restarting");
+            if let Err(e) = self.stop(self.config.stopsignal, true) {
+                warn!(process = self.name.as_str(); "{:#}", e);
+            }
+        } else if let Err(e) = self.reopen_pipes() {
+            warn!(process = self.name.as_str(); "{:#}", e);
+        }
+    }
+
+    fn restore_running(&mut self, saved: &SavedProcess) {
+        let spawned_at = UNIX_EPOCH + Duration::from_millis(saved.spawned_at);
+        let elapsed = spawned_at.elapsed().unwrap_or_default();
+        let start = Instant::now()
+            .checked_sub(elapsed)
+            .unwrap_or_else(Instant::now);
+        let status = match saved.status {
+            SavedStatus::Starting { tries } => RunningStatus::StartRequested { start, tries },
+            SavedStatus::Running => RunningStatus::Running {
+                health: self.health(start),
+            },
+            // the stop timeout starts over
+            SavedStatus::Stopping { restart } => RunningStatus::StopRequested {
+                since: Instant::now(),
+                restart,
+            },
+        };
+        self.state = State::Running {
+            pid: Pid::from_raw(saved.pid),
+            status,
+        };
+        self.starttime = saved.starttime;
+        self.spawned_at = spawned_at;
+        self.arm_watchdog();
+    }
+
+    /// Describe the process for the next image of the daemon, its fds stay open
+    pub fn hand_off(&self) -> ProcessHandoff {
+        let state = match (&self.state, self.saved()) {
+            (_, Some(saved)) => HandedState::Running(saved),
+            (State::Stopped(StoppedStatus::Backoff { tries, .. }), None) => {
+                HandedState::Backoff { tries: *tries }
+            }
+            (State::Stopped(StoppedStatus::Fatal), None) => HandedState::Fatal,
+            (State::Stopped(StoppedStatus::Unexpected), None) => HandedState::Unexpected,
+            (State::Stopped(StoppedStatus::Exited), None) => HandedState::Exited,
+            (State::Stopped(StoppedStatus::Stopped), None) | (State::Running { .. }, None) => {
+                HandedState::Stopped
+            }
+        };
+        ProcessHandoff {
+            state,
+            pidfd: self
+                .adopted
+                .as_ref()
+                .and_then(|adopted| adopted.pidfd.as_ref())
+                .map(AsRawFd::as_raw_fd),
+            adopted: self.adopted.is_some(),
+            stdin: self.stdin.as_ref().map(AsRawFd::as_raw_fd),
+            outputs: self
+                .outputs
+                .iter()
+                .map(|pipe| (pipe.as_raw_fd(), pipe.stream))
+                .collect(),
+            sessions: self.sessions.iter().map(|pid| pid.as_raw()).collect(),
+            orphans: self.orphans.iter().map(|pid| pid.as_raw()).collect(),
+            logs: self.stdout_log.is_some() || self.stderr_log.is_some(),
+            stdout_buffer: self.stdout_buffer.tail(usize::MAX),
+            stderr_buffer: self.stderr_buffer.tail(usize::MAX),
+            notify: self.notify.as_ref().map(AsRawFd::as_raw_fd),
+            notice: self.notice.clone(),
+            stopping: self.stopping.is_some(),
+        }
+    }
+
+    /// Resume the supervision of a process handed over by the previous image of the daemon
+    pub fn take_over(&mut self, handoff: ProcessHandoff) -> Result<()> {
+        match &handoff.state {
+            HandedState::Running(saved) => self.restore_running(saved),
+            HandedState::Backoff { tries } => {
+                self.state = State::Stopped(StoppedStatus::Backoff {
+                    tries: *tries,
+                    started_at: Instant::now(),
+                })
+            }
+            HandedState::Fatal => self.state = State::Stopped(StoppedStatus::Fatal),
+            HandedState::Unexpected => self.state = State::Stopped(StoppedStatus::Unexpected),
+            HandedState::Exited => self.state = State::Stopped(StoppedStatus::Exited),
+            HandedState::Stopped => self.state = State::Stopped(StoppedStatus::Stopped),
+        }
+        // SAFETY: the fds were left open by the previous image, which does not exist anymore
+        let owned = |fd| unsafe { OwnedFd::from_raw_fd(fd) };
+        if handoff.adopted {
+            self.adopted = Some(Adopted {
+                pidfd: handoff.pidfd.map(owned),
+                registered: false,
+            });
+        }
+        self.stdin = handoff.stdin.map(|fd| File::from(owned(fd)));
+        self.notify = handoff.notify.map(|fd| NotifySocket::from_fd(owned(fd)));
+        self.notice = handoff.notice;
+        if handoff.stopping {
+            self.set_stopping();
+        }
+        self.outputs = handoff
+            .outputs
+            .into_iter()
+            .map(|(fd, stream)| Pipe::new(owned(fd), stream))
+            .collect::<Result<_>>()?;
+        self.sessions = handoff.sessions.into_iter().map(Pid::from_raw).collect();
+        self.orphans = handoff.orphans.into_iter().map(Pid::from_raw).collect();
+        self.stdout_buffer.push(&handoff.stdout_buffer);
+        self.stderr_buffer.push(&handoff.stderr_buffer);
+        if handoff.logs {
+            self.reopen_logs()?;
         }
         Ok(())
     }
 
+    /// Pidfd of the adopted process, if the main loop does not watch it yet
+    pub fn unregistered_pidfd(&mut self) -> Option<RawFd> {
+        let adopted = self
+            .adopted
+            .as_mut()
+            .filter(|adopted| !adopted.registered)?;
+        adopted.registered = true;
+        adopted.pidfd.as_ref().map(AsRawFd::as_raw_fd)
+    }
+
+    /// Whether `fd` is the pidfd of the process
+    pub fn has_pidfd(&self, fd: RawFd) -> bool {
+        self.adopted
+            .as_ref()
+            .and_then(|adopted| adopted.pidfd.as_ref())
+            .is_some_and(|pidfd| pidfd.as_raw_fd() == fd)
+    }
+
+    /// Whether the process was adopted without a pidfd and is gone
+    pub fn adopted_is_gone(&self) -> bool {
+        match (&self.adopted, self.pid()) {
+            (Some(Adopted { pidfd: None, .. }), Some(pid)) => {
+                procfs::stat(pid).map(|stat| stat.starttime) != Some(self.starttime)
+            }
+            _ => false,
+        }
+    }
+
     pub fn is_running(&self) -> bool {
         match &self.state {
             State::Stopped(_) => false,
             State::Running { .. } => true,
         }
     }
-}
 
-impl Display for Process {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{}: {}", self.name, self.state)
+    /// Whether the process was asked to stop, and not to start again
+    pub fn is_stopping(&self) -> bool {
+        matches!(
+            self.state,
+            State::Running {
+                status: RunningStatus::StopRequested { restart: false, .. },
+                ..
+            }
+        )
     }
 }
 
-impl Drop for Process {
-    fn drop(&mut self) {
-        if let State::Running { .. } = &self.state {
-            panic!("Process {} was not stopped", self.name);
-        }
+/// Command running `cmd` in the working directory and environment of the program
+fn command(cmd: &Cmd, config: &JobConfig) -> Result<Command> {
+    let path = config
+        .env
+        .as_ref()
+        .and_then(|env| env.0.get("PATH"))
+        .map(String::as_str);
+    let mut command = Command::new(cmd.resolve(path, &config.workingdir.0)?);
+    command.arg0(cmd.program());
+    command.args(cmd.args());
+    command.current_dir(config.workingdir.0.clone());
+    if let Some(user) = &config.user {
+        command.env("HOME", &user.home);
+        command.env("USER", &user.name);
+        command.env("LOGNAME", &user.name);
+    }
+    if let Some(env) = &config.env {
+        command.envs(env.0.iter());
     }
+    Ok(command)
 }
@@ -0,0 +1,379 @@
+use super::setup::ChildSetup;
+use crate::job::jobconfig::cmd::Cmd;
+use crate::job::jobconfig::healthcheck::{Check, HealthCheck};
+use crate::job::jobconfig::JobConfig;
+use anyhow::Result;
+use log::{debug, info, warn};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::signal::{killpg, Signal};
+use nix::sys::socket::{
+    connect, getsockopt, socket, sockopt, AddressFamily, SockFlag, SockType, SockaddrIn,
+    SockaddrIn6, SockaddrLike, UnixAddr,
+};
+use nix::unistd::Pid;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use taskmasterproto::Health as HealthState;
+
+/// Size of the HTTP response after which the status line is given up on
+const MAX_STATUS_LINE: usize = 4096;
+
+/// Health checks of a running process
+#[derive(Debug)]
+pub struct Health {
+    pub state: HealthState,
+    /// Number of consecutive failed checks
+    pub failures: u32,
+    /// Failures are not counted before this time, unless a check already succeeded
+    grace_until: Instant,
+    /// Time at which the next check starts, or at which the running one times out
+    next_at: Instant,
+    probe: Option<Probe>,
+}
+
+/// A check in progress
+#[derive(Debug)]
+enum Probe {
+    /// Command whose exit is reaped by the main loop, killed if it does not complete
+    Exec { pid: Pid, reaped: bool },
+    /// Connection being established, then HTTP request being sent and response being read
+    Connect {
+        socket: File,
+        connected: bool,
+        http: Option<Http>,
+        /// Whether the main loop already watches the socket
+        registered: bool,
+    },
+}
+
+#[derive(Debug)]
+struct Http {
+    request: Vec<u8>,
+    sent: usize,
+    response: Vec<u8>,
+}
+
+impl Health {
+    /// Checks of a process that started at `started`, the first one runs right away
+    pub fn new(healthcheck: &HealthCheck, started: Instant) -> Self {
+        Self {
+            state: HealthState::Starting,
+            failures: 0,
+            grace_until: started + Duration::from_secs(healthcheck.start_period),
+            next_at: Instant::now(),
+            probe: None,
+        }
+    }
+
+    /// Time at which the next check starts, or at which the running one times out
+    ///
+    /// The connection of a running check is polled as the main loop sees it progress.
+    pub fn deadline(&self) -> Instant {
+        self.next_at
+    }
+
+    /// Connection of the running check, if the main loop does not watch it yet
+    pub fn unregistered_probe(&mut self) -> Option<RawFd> {
+        match &mut self.probe {
+            Some(Probe::Connect {
+                socket, registered, ..
+            }) if !*registered => {
+                *registered = true;
+                Some(socket.as_raw_fd())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `fd` is the connection of the running check
+    pub fn has_probe(&self, fd: RawFd) -> bool {
+        matches!(&self.probe, Some(Probe::Connect { socket, .. }) if socket.as_raw_fd() == fd)
+    }
+
+    /// Start a check when it is time, and see whether the running one completed
+    ///
+    /// Returns the result of the check that completed, if any.
+    pub fn poll(&mut self, config: &JobConfig, now: Instant) -> Option<bool> {
+        let healthcheck = config.healthcheck.as_ref()?;
+        let result = match &mut self.probe {
+            None if now >= self.next_at => match Probe::start(&healthcheck.check, config) {
+                Ok(mut probe) => {
+                    self.next_at = now + Duration::from_secs(healthcheck.timeout.get());
+                    // a connection can be established right away
+                    let result = probe.poll();
+                    self.probe = Some(probe);
+                    result
+                }
+                Err(e) => {
+                    debug!("Failed to start health check: {:#}", e);
+                    Some(false)
+                }
+            },
+            None => None,
+            Some(_) if now >= self.next_at => {
+                debug!("Health check timed out");
+                Some(false)
+            }
+            Some(probe) => probe.poll(),
+        };
+        if result.is_some() {
+            self.probe = None;
+            self.next_at = now + Duration::from_secs(healthcheck.interval.get());
+        }
+        result
+    }
+
+    /// Take the result of the command of an exec check, if `pid` is its process
+    pub fn reap(&mut self, config: &JobConfig, pid: Pid, code: Option<i32>) -> Option<bool> {
+        let healthcheck = config.healthcheck.as_ref()?;
+        match &mut self.probe {
+            Some(Probe::Exec { pid: probe, reaped }) if *probe == pid => *reaped = true,
+            _ => return None,
+        }
+        self.probe = None;
+        self.next_at = Instant::now() + Duration::from_secs(healthcheck.interval.get());
+        Some(code == Some(0))
+    }
+
+    /// Account for the result of a check
+    ///
+    /// Returns `true` once the process failed enough consecutive checks to be restarted.
+    pub fn record(&mut self, process: &str, config: &JobConfig, success: bool) -> bool {
+        let Some(healthcheck) = &config.healthcheck else {
+            return false;
+        };
+        if success {
+            if self.state != HealthState::Healthy {
+                info!(process = process, transition = "HEALTHY"; "health check succeeded");
+            }
+            self.state = HealthState::Healthy;
+            self.failures = 0;
+            return false;
+        }
+        if self.state == HealthState::Starting && Instant::now() < self.grace_until {
+            debug!(process = process; "health check failed during the start period");
+            return false;
+        }
+        self.failures += 1;
+        if self.state == HealthState::Unhealthy {
+            warn!(
+                process = process, failures = self.failures;
+                "health check failed ({}/{})", self.failures, healthcheck.retries
+            );
+        } else {
+            warn!(
+                process = process, transition = "UNHEALTHY", failures = self.failures;
+                "health check failed ({}/{})", self.failures, healthcheck.retries
+            );
+        }
+        self.state = HealthState::Unhealthy;
+        self.failures >= healthcheck.retries.get()
+    }
+}
+
+impl Probe {
+    fn start(check: &Check, config: &JobConfig) -> Result<Self> {
+        let (address, http): (Box<dyn SockaddrLike>, _) = match check {
+            Check::Exec { cmd } => return Self::exec(cmd, config),
+            Check::Tcp { host, port } => (sockaddr(SocketAddr::new(*host, *port)), None),
+            Check::Http { host, port, path } => {
+                let address = SocketAddr::new(*host, *port);
+                let request = format!(
+                    "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                    path, address
+                );
+                let http = Http {
+                    request: request.into_bytes(),
+                    sent: 0,
+                    response: Vec::new(),
+                };
+                (sockaddr(address), Some(http))
+            }
+            Check::Unix { path } => (Box::new(UnixAddr::new(path)?), None),
+        };
+        let family = address.family().unwrap_or(AddressFamily::Unix);
+        let fd = socket(
+            family,
+            SockType::Stream,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        // SAFETY: socket returned an fd that nothing else owns
+        let socket = unsafe { File::from_raw_fd(fd) };
+        match connect(socket.as_raw_fd(), address.as_ref()) {
+            Ok(()) | Err(Errno::EINPROGRESS) => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Probe::Connect {
+            socket,
+            connected: false,
+            http,
+            registered: false,
+        })
+    }
+
+    fn exec(cmd: &Cmd, config: &JobConfig) -> Result<Self> {
+        let mut command = super::command(cmd, config)?;
+        command.stdin(Stdio::null());
+        command.stdout(Stdio::null());
+        command.stderr(Stdio::null());
+        let setup = ChildSetup::new(config)?.without_tty();
+        // SAFETY: only async-signal-safe calls are made between fork and exec
+        unsafe {
+            command.pre_exec(move || Ok(setup.apply()?));
+        }
+        let child = command.spawn()?;
+        Ok(Probe::Exec {
+            pid: Pid::from_raw(child.id() as i32),
+            reaped: false,
+        })
+    }
+
+    /// Returns the result of the check once it completed, exec checks complete when reaped
+    fn poll(&mut self) -> Option<bool> {
+        let Probe::Connect {
+            socket,
+            connected,
+            http,
+            ..
+        } = self
+        else {
+            return None;
+        };
+        if !*connected {
+            let mut fds = [PollFd::new(socket.as_raw_fd(), PollFlags::POLLOUT)];
+            match poll(&mut fds, 0) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(_) => return Some(false),
+            }
+            if getsockopt(socket.as_raw_fd(), sockopt::SocketError) != Ok(0) {
+                return Some(false);
+            }
+            *connected = true;
+        }
+        match http {
+            Some(http) => http.poll(socket),
+            None => Some(true),
+        }
+    }
+}
+
+impl Drop for Probe {
+    fn drop(&mut self) {
+        if let Probe::Exec { pid, reaped: false } = self {
+            // the command leads its own process group, its exit is still reaped by the main loop
+            let _ = killpg(*pid, Signal::SIGKILL);
+        }
+    }
+}
+
+impl Http {
+    /// Send the request then read the status line, returns whether it is a 2xx or 3xx
+    fn poll(&mut self, socket: &mut File) -> Option<bool> {
+        while self.sent < self.request.len() {
+            match socket.write(&self.request[self.sent..]) {
+                Ok(n) => self.sent += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return None,
+                Err(_) => return Some(false),
+            }
+        }
+        let mut buf = [0; 1024];
+        while !self.response.windows(2).any(|w| w == b"\r\n")
+            && self.response.len() < MAX_STATUS_LINE
+        {
+            match socket.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => self.response.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return None,
+                Err(_) => return Some(false),
+            }
+        }
+        let status = String::from_utf8_lossy(&self.response)
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok());
+        Some(matches!(status, Some(200..=399)))
+    }
+}
+
+fn sockaddr(address: SocketAddr) -> Box<dyn SockaddrLike> {
+    match address {
+        SocketAddr::V4(address) => Box::new(SockaddrIn::from(address)),
+        SocketAddr::V6(address) => Box::new(SockaddrIn6::from(address)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn config(healthcheck: &str) -> JobConfig {
+        let mut config: JobConfig = serde_yaml::from_str("cmd: sleep 1").unwrap();
+        config.healthcheck = Some(serde_yaml::from_str(healthcheck).unwrap());
+        config
+    }
+
+    /// Run a single check to completion
+    fn check(config: &JobConfig) -> bool {
+        let mut health = Health::new(config.healthcheck.as_ref().unwrap(), Instant::now());
+        loop {
+            if let Some(result) = health.poll(config, Instant::now()) {
+                return result;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_tcp_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = config(&format!("type: tcp\nport: {}", port));
+        assert!(check(&config));
+        drop(listener);
+        assert!(!check(&config));
+    }
+
+    #[test]
+    fn test_http_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            for status in ["200 OK", "503 Service Unavailable"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let n = stream.read(&mut request).unwrap();
+                assert!(request[..n].starts_with(b"GET /health HTTP/1.0\r\n"));
+                write!(stream, "HTTP/1.0 {}\r\n\r\n", status).unwrap();
+            }
+        });
+        let config = config(&format!("type: http\nport: {}\npath: /health", port));
+        assert!(check(&config));
+        assert!(!check(&config));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_record() {
+        let config = config("type: tcp\nport: 1\nretries: 2\nstart_period: 60");
+        let mut health = Health::new(config.healthcheck.as_ref().unwrap(), Instant::now());
+        // failures during the start period are not counted
+        assert!(!health.record("test", &config, false));
+        assert_eq!(health.state, HealthState::Starting);
+        assert!(!health.record("test", &config, true));
+        assert!(!health.record("test", &config, false));
+        assert_eq!(health.state, HealthState::Unhealthy);
+        assert!(!health.record("test", &config, true));
+        assert!(!health.record("test", &config, false));
+        assert!(health.record("test", &config, false));
+    }
+}
@@ -0,0 +1,336 @@
+use crate::job::jobconfig::LogConfig;
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::error;
+use nix::sys::signal::SigSet;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Rotated files waiting to be compressed, along with the backups they go to
+static COMPRESSOR: OnceLock<Sender<(Backups, PathBuf)>> = OnceLock::new();
+
+/// Log file of an output stream, rotated once it reaches its maximum size
+///
+/// Rotated files are renamed `path.1`, `path.2`... up to the number of backups, the
+/// oldest one being dropped, and gzipped to `path.1.gz`... if compression is enabled.
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    maxbytes: u64,
+    backups: Backups,
+    file: File,
+    size: u64,
+    /// Time of the last rotation in nanoseconds since the epoch, naming the rotated file
+    /// waiting to be compressed
+    rotated_at: u128,
+}
+
+/// Names of the rotated files of a log
+#[derive(Debug, Clone)]
+struct Backups {
+    path: PathBuf,
+    count: u32,
+    compress: bool,
+}
+
+impl LogFile {
+    pub fn open(config: &LogConfig) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(config.append)
+            .truncate(!config.append)
+            .open(config.path)
+            .with_context(|| format!("Failed to open log file {}", config.path.display()))?;
+        let size = file.metadata()?.len();
+        let log = Self {
+            path: config.path.to_path_buf(),
+            maxbytes: config.maxbytes,
+            backups: Backups {
+                path: config.path.to_path_buf(),
+                count: config.backups,
+                compress: config.compress,
+            },
+            file,
+            size,
+            rotated_at: 0,
+        };
+        if log.backups.count > 0 {
+            for rotated in log.backups.leftovers() {
+                log.push(rotated)?;
+            }
+        }
+        Ok(log)
+    }
+
+    /// Append `data`, rotating the file first if it would grow past its maximum size
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.maxbytes > 0 && self.size > 0 && self.size + data.len() as u64 > self.maxbytes {
+            self.rotate()?;
+        }
+        self.file
+            .write_all(data)
+            .with_context(|| format!("Failed to write to {}", self.path.display()))?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Move the current file to the first backup and start a new one
+    ///
+    /// With compression, the file is only renamed here, and moved to the first backup by
+    /// the compression thread so that compressing it does not hold up the caller.
+    pub fn rotate(&mut self) -> Result<()> {
+        if self.backups.count > 0 && self.backups.compress {
+            // named after the time, so that a file left by a previous daemon is not reused
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            self.rotated_at = now.max(self.rotated_at + 1);
+            let rotated = self
+                .backups
+                .numbered(&format!("rotated-{:020}", self.rotated_at));
+            rename_if_exists(&self.path, &rotated)?;
+            self.push(rotated)?;
+        } else if self.backups.count > 0 {
+            self.backups.push(&self.path)?;
+        }
+        self.file = File::create(&self.path)
+            .with_context(|| format!("Failed to create log file {}", self.path.display()))?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Open the file at the log path again, after it was moved by another program
+    pub fn reopen(&mut self) -> Result<()> {
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to reopen log file {}", self.path.display()))?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+
+    /// Move a rotated file to the first backup, by the compression thread if compressed
+    fn push(&self, rotated: PathBuf) -> Result<()> {
+        if !self.backups.compress {
+            return self.backups.push(&rotated);
+        }
+        if let Err(mpsc::SendError((_, rotated))) =
+            compressor().send((self.backups.clone(), rotated))
+        {
+            // the thread is gone, the file is compressed here instead
+            self.backups.push(&rotated)?;
+        }
+        Ok(())
+    }
+}
+
+/// Channel to the thread compressing the rotated files of every log, in the order they were
+/// rotated, started on first use
+fn compressor() -> &'static Sender<(Backups, PathBuf)> {
+    COMPRESSOR.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<(Backups, PathBuf)>();
+        thread::spawn(move || {
+            // signals are left to the main thread, which reads them from a signalfd
+            let _ = SigSet::all().thread_block();
+            for (backups, rotated) in receiver {
+                if let Err(e) = backups.push(&rotated) {
+                    error!("{:#}", e);
+                }
+            }
+        });
+        sender
+    })
+}
+
+impl Backups {
+    fn numbered(&self, suffix: &str) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{}", suffix));
+        PathBuf::from(path)
+    }
+
+    fn backup(&self, i: u32) -> PathBuf {
+        let mut path = self.numbered(&i.to_string()).into_os_string();
+        if self.compress {
+            path.push(".gz");
+        }
+        PathBuf::from(path)
+    }
+
+    /// Rotated files a previous daemon left before moving them to the backups, oldest first
+    fn leftovers(&self) -> Vec<PathBuf> {
+        let mut prefix = OsString::from(self.path.file_name().unwrap_or_default());
+        prefix.push(".rotated-");
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut leftovers = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|name| name.as_bytes().starts_with(prefix.as_bytes()))
+            })
+            .collect::<Vec<_>>();
+        leftovers.sort();
+        leftovers
+    }
+
+    /// Shift the backups, dropping the oldest one, and move `rotated` to the first one
+    fn push(&self, rotated: &Path) -> Result<()> {
+        for i in (1..self.count).rev() {
+            rename_if_exists(&self.backup(i), &self.backup(i + 1))?;
+        }
+        if self.compress {
+            gzip(rotated, &self.backup(1))
+                .with_context(|| format!("Failed to compress {}", rotated.display()))
+        } else {
+            rename_if_exists(rotated, &self.backup(1))
+        }
+    }
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e)
+            .with_context(|| format!("Failed to rename {} to {}", from.display(), to.display())),
+        _ => Ok(()),
+    }
+}
+
+/// Compress `from` to `to`, removing `from`
+///
+/// `to` only appears once complete.
+fn gzip(from: &Path, to: &Path) -> Result<()> {
+    let mut partial = OsString::from(to);
+    partial.push(".partial");
+    let mut encoder = GzEncoder::new(File::create(&partial)?, Compression::default());
+    io::copy(&mut File::open(from)?, &mut encoder)?;
+    encoder.finish()?;
+    fs::rename(&partial, to)?;
+    fs::remove_file(from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("taskmasterd-logfile-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.log");
+        let mut log = LogFile::open(&LogConfig {
+            path: &path,
+            maxbytes: 10,
+            backups: 2,
+            compress: false,
+            append: false,
+        })
+        .unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            log.write(line.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("out.log.1")).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("out.log.2")).unwrap(),
+            "second\n"
+        );
+        assert!(!dir.join("out.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Names of the files in `dir` once they are `expected`, or after a timeout
+    fn files(dir: &Path, expected: &[&str]) -> Vec<String> {
+        use std::time::{Duration, Instant};
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let mut files = fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect::<Vec<_>>();
+            files.sort_unstable();
+            if files == expected || Instant::now() > deadline {
+                return files;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn gunzip(path: &Path) -> String {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let mut content = String::new();
+        GzDecoder::new(File::open(path).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn test_compressed_rotation() {
+        let dir = std::env::temp_dir().join(format!("taskmasterd-gzip-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.log");
+        let mut log = LogFile::open(&LogConfig {
+            path: &path,
+            maxbytes: 10,
+            backups: 2,
+            compress: true,
+            append: false,
+        })
+        .unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            log.write(line.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        // the rotated files are compressed in the background
+        let expected = ["out.log", "out.log.1.gz", "out.log.2.gz"];
+        assert_eq!(files(&dir, &expected), expected);
+        assert_eq!(gunzip(&dir.join("out.log.1.gz")), "third\n");
+        assert_eq!(gunzip(&dir.join("out.log.2.gz")), "second\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_leftover_rotation() {
+        let dir = std::env::temp_dir().join(format!("taskmasterd-leftover-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // rotated by a daemon that crashed before compressing it
+        fs::write(dir.join("out.log.rotated-1"), "before the crash\n").unwrap();
+        let _log = LogFile::open(&LogConfig {
+            path: &dir.join("out.log"),
+            maxbytes: 10,
+            backups: 2,
+            compress: true,
+            append: true,
+        })
+        .unwrap();
+        let expected = ["out.log", "out.log.1.gz"];
+        assert_eq!(files(&dir, &expected), expected);
+        assert_eq!(gunzip(&dir.join("out.log.1.gz")), "before the crash\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
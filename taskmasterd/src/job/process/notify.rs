@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use log::debug;
+use nix::cmsg_space;
+use nix::errno::Errno;
+use nix::sys::socket::{
+    bind, recvmsg, setsockopt, socket, sockopt, AddressFamily, ControlMessageOwned, MsgFlags,
+    SockFlag, SockType, UnixAddr, UnixCredentials,
+};
+use nix::unistd::Pid;
+use std::io::IoSliceMut;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// Size of the datagrams read, longer ones are truncated
+const MAX_DATAGRAM: usize = 4096;
+
+/// Datagram socket a process sends its notifications to, as with systemd's `sd_notify`
+#[derive(Debug)]
+pub struct NotifySocket {
+    socket: OwnedFd,
+    /// Whether the main loop already watches the socket
+    pub registered: bool,
+}
+
+/// A notification sent by a process, other assignments are ignored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    /// `READY=1`, the process is done starting
+    Ready,
+    /// `STATUS=...`, a free-form description of what the process is doing
+    Status(String),
+    /// `STOPPING=1`, the process is shutting down on its own
+    Stopping,
+    /// `WATCHDOG=1`, the process is still alive
+    Watchdog,
+    /// `WATCHDOG=trigger`, the process asks to be handled as if its watchdog expired
+    WatchdogTrigger,
+}
+
+impl NotifySocket {
+    /// Bind a socket to `name` in the abstract namespace, which processes reach whatever
+    /// user they run as
+    pub fn bind(name: &str) -> Result<Self> {
+        let fd = socket(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .context("Failed to create the notify socket")?;
+        // SAFETY: socket returned an fd that nothing else owns
+        let socket = Self::from_fd(unsafe { OwnedFd::from_raw_fd(fd) });
+        // the sender of each datagram is checked
+        setsockopt(fd, sockopt::PassCred, &true)?;
+        bind(fd, &UnixAddr::new_abstract(name.as_bytes())?)
+            .with_context(|| format!("Failed to bind the notify socket @{}", name))?;
+        Ok(socket)
+    }
+
+    /// Socket bound by the previous image of the daemon
+    pub fn from_fd(socket: OwnedFd) -> Self {
+        Self {
+            socket,
+            registered: false,
+        }
+    }
+
+    /// Read the pending datagrams, with the pid of the process that sent each of them
+    pub fn receive(&self) -> Vec<(Pid, Vec<Notification>)> {
+        let mut received = Vec::new();
+        let mut buffer = [0; MAX_DATAGRAM];
+        let mut cmsg = cmsg_space!(UnixCredentials);
+        loop {
+            let mut iov = [IoSliceMut::new(&mut buffer)];
+            let (len, sender) = match recvmsg::<()>(
+                self.socket.as_raw_fd(),
+                &mut iov,
+                Some(&mut cmsg),
+                MsgFlags::MSG_DONTWAIT,
+            ) {
+                Ok(msg) => {
+                    let sender = msg.cmsgs().find_map(|cmsg| match cmsg {
+                        ControlMessageOwned::ScmCredentials(credentials) => {
+                            Some(Pid::from_raw(credentials.pid()))
+                        }
+                        _ => None,
+                    });
+                    (msg.bytes, sender)
+                }
+                Err(Errno::EINTR) => continue,
+                Err(Errno::EAGAIN) => break,
+                Err(e) => {
+                    debug!("Failed to read the notify socket: {}", e);
+                    break;
+                }
+            };
+            if let Some(sender) = sender {
+                received.push((sender, parse(&String::from_utf8_lossy(&buffer[..len]))));
+            }
+        }
+        received
+    }
+}
+
+impl AsRawFd for NotifySocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+/// Parse a datagram made of newline separated assignments
+pub fn parse(datagram: &str) -> Vec<Notification> {
+    datagram
+        .lines()
+        .filter_map(|line| match line.split_once('=')? {
+            ("READY", "1") => Some(Notification::Ready),
+            ("STATUS", status) => Some(Notification::Status(status.to_string())),
+            ("STOPPING", "1") => Some(Notification::Stopping),
+            ("WATCHDOG", "1") => Some(Notification::Watchdog),
+            ("WATCHDOG", "trigger") => Some(Notification::WatchdogTrigger),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("READY=1\nSTATUS=Listening on port 80\nMAINPID=42\nWATCHDOG=trigger\n"),
+            [
+                Notification::Ready,
+                Notification::Status("Listening on port 80".to_string()),
+                Notification::WatchdogTrigger,
+            ]
+        );
+        assert_eq!(
+            parse("STOPPING=1\nWATCHDOG=1"),
+            [Notification::Stopping, Notification::Watchdog]
+        );
+        assert!(parse("READY=0\ngarbage").is_empty());
+    }
+
+    #[test]
+    fn test_receive() {
+        let name = format!("taskmasterd-test/{}", std::process::id());
+        let socket = NotifySocket::bind(&name).unwrap();
+        let client = UnixDatagram::unbound().unwrap();
+        let address = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        client.send_to_addr(b"READY=1", &address).unwrap();
+        client.send_to_addr(b"STATUS=up", &address).unwrap();
+        let pid = Pid::from_raw(std::process::id() as i32);
+        assert_eq!(
+            socket.receive(),
+            [
+                (pid, vec![Notification::Ready]),
+                (pid, vec![Notification::Status("up".to_string())]),
+            ]
+        );
+        assert!(socket.receive().is_empty());
+    }
+}
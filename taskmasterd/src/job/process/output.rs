@@ -0,0 +1,117 @@
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::pty::openpty;
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+
+/// Output stream of a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// Non-blocking read end of a pipe, or master side of a pseudo-terminal, the process writes
+/// one of its streams to
+#[derive(Debug)]
+pub struct Pipe {
+    file: File,
+    pub stream: Stream,
+    /// Whether the main loop already watches the pipe
+    pub registered: bool,
+}
+
+impl Pipe {
+    pub fn new(fd: OwnedFd, stream: Stream) -> Result<Self> {
+        set_nonblocking(&fd).context("Failed to set output pipe to non-blocking")?;
+        Ok(Self {
+            file: File::from(fd),
+            stream,
+            registered: false,
+        })
+    }
+
+    pub fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buffer)
+    }
+
+    /// Whether this is the read end of the named pipe at `path`
+    pub fn reads(&self, path: &Path) -> bool {
+        match (self.file.metadata(), fs::metadata(path)) {
+            (Ok(pipe), Ok(fifo)) => pipe.dev() == fifo.dev() && pipe.ino() == fifo.ino(),
+            _ => false,
+        }
+    }
+}
+
+pub fn set_nonblocking(fd: &impl AsRawFd) -> nix::Result<()> {
+    fcntl(fd.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+    Ok(())
+}
+
+/// Open a pseudo-terminal, returning its master and slave sides
+pub fn open_pty() -> Result<(File, File)> {
+    let pty = openpty(None, None).context("Failed to open a pseudo-terminal")?;
+    // SAFETY: openpty returned two fds that nothing else owns
+    let (master, slave) = unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+    for fd in [&master, &slave] {
+        fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+            .context("Failed to set close-on-exec on the pseudo-terminal")?;
+    }
+    Ok((master, slave))
+}
+
+/// Create the named pipe at `path`, unless it already exists
+pub fn make_fifo(path: &Path) -> Result<()> {
+    match mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR) {
+        Ok(()) => Ok(()),
+        Err(Errno::EEXIST) if fs::metadata(path)?.file_type().is_fifo() => Ok(()),
+        Err(Errno::EEXIST) => bail!("{} exists and is not a named pipe", path.display()),
+        Err(e) => Err(e).with_context(|| format!("Failed to create {}", path.display())),
+    }
+}
+
+/// Open the named pipe at `path` for the process, both for reading and writing
+///
+/// Holding both ends, the process never gets SIGPIPE nor end of file while the daemon does
+/// not hold the other one, it only blocks once the pipe is full or empty.
+pub fn open_fifo(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+/// Open the non-blocking end the daemon holds of the named pipe at `path`
+pub fn open_fifo_end(path: &Path, write: bool) -> Result<File> {
+    OpenOptions::new()
+        .read(!write)
+        .write(write)
+        .custom_flags(OFlag::O_NONBLOCK.bits())
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+impl AsRawFd for Pipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
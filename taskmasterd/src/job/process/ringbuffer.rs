@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+
+/// The last bytes written to a stream, up to a fixed capacity
+#[derive(Debug)]
+pub struct RingBuffer {
+    data: VecDeque<u8>,
+    capacity: usize,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Append `data`, dropping the oldest bytes beyond the capacity
+    pub fn push(&mut self, data: &[u8]) {
+        let data = &data[data.len().saturating_sub(self.capacity)..];
+        let excess = (self.data.len() + data.len()).saturating_sub(self.capacity);
+        self.data.drain(..excess);
+        self.data.extend(data);
+    }
+
+    /// The last `lines` lines, the last one may be incomplete
+    pub fn tail(&self, lines: usize) -> Vec<u8> {
+        if lines == 0 {
+            return Vec::new();
+        }
+        // a trailing newline ends the last line, it does not start a new one
+        let end = self.data.len().saturating_sub(1);
+        let start = self
+            .data
+            .range(..end)
+            .enumerate()
+            .rev()
+            .filter(|(_, byte)| **byte == b'\n')
+            .nth(lines - 1)
+            .map_or(0, |(i, _)| i + 1);
+        self.data.range(start..).copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut buffer = RingBuffer::new(16);
+        buffer.push(b"one\ntwo\n");
+        assert_eq!(buffer.tail(1), b"two\n");
+        assert_eq!(buffer.tail(5), b"one\ntwo\n");
+        buffer.push(b"three\nfour\nfive");
+        // "one\ntwo" was dropped
+        assert_eq!(buffer.tail(10), b"\nthree\nfour\nfive");
+        assert_eq!(buffer.tail(2), b"four\nfive");
+        assert_eq!(buffer.tail(0), b"");
+        buffer.push(b"a very long line that does not fit\n");
+        assert_eq!(buffer.tail(1), b"at does not fit\n");
+    }
+}
@@ -0,0 +1,86 @@
+use crate::job::jobconfig::rlimits::RLimit;
+use crate::job::jobconfig::{Credentials, JobConfig};
+use anyhow::Result;
+use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::libc;
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::sys::resource::{setrlimit, Resource};
+use nix::sys::signal::SigSet;
+use nix::sys::stat::{umask, Mode};
+use nix::unistd::{close, setgid, setgroups, setsid, setuid, write, Pid};
+
+/// Attributes applied to a child between fork and exec
+///
+/// Everything is computed beforehand, so that `apply` only makes async-signal-safe calls.
+pub struct ChildSetup {
+    umask: Option<Mode>,
+    rlimits: Vec<(Resource, RLimit)>,
+    nice: Option<i32>,
+    oom_score_adj: Option<String>,
+    cpu_affinity: Option<CpuSet>,
+    controlling_tty: bool,
+    credentials: Option<Credentials>,
+}
+
+impl ChildSetup {
+    pub fn new(config: &JobConfig) -> Result<Self> {
+        Ok(Self {
+            umask: config.umask.map(|umask| Mode::from_bits_truncate(umask.0)),
+            rlimits: config.rlimits.resources(),
+            nice: config.nice.map(|nice| nice.0),
+            oom_score_adj: config.oom_score_adj.map(|adj| adj.0.to_string()),
+            cpu_affinity: config.cpu_affinity.as_ref().map(|cpus| cpus.cpu_set()),
+            controlling_tty: config.tty,
+            credentials: config.credentials()?,
+        })
+    }
+
+    /// Do not make the pseudo-terminal the controlling terminal, for commands that do not
+    /// run on it
+    pub fn without_tty(mut self) -> Self {
+        self.controlling_tty = false;
+        self
+    }
+
+    /// Apply the attributes to the current process, privileges are dropped last
+    pub fn apply(&self) -> nix::Result<()> {
+        // the daemon blocks the signals it reads through its signalfd
+        SigSet::all().thread_unblock()?;
+        // each process leads its own session and process group
+        setsid()?;
+        if self.controlling_tty {
+            // SAFETY: TIOCSCTTY takes no pointer, stdin is the pseudo-terminal at this point
+            if unsafe { libc::ioctl(0, libc::TIOCSCTTY, 0) } == -1 {
+                return Err(Errno::last());
+            }
+        }
+        if let Some(mode) = self.umask {
+            umask(mode);
+        }
+        for (resource, limit) in &self.rlimits {
+            setrlimit(*resource, limit.soft, limit.hard)?;
+        }
+        if let Some(nice) = self.nice {
+            // SAFETY: setpriority has no memory safety requirements
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } == -1 {
+                return Err(Errno::last());
+            }
+        }
+        if let Some(adj) = &self.oom_score_adj {
+            let fd = open("/proc/self/oom_score_adj", OFlag::O_WRONLY, Mode::empty())?;
+            let written = write(fd, adj.as_bytes());
+            close(fd)?;
+            written?;
+        }
+        if let Some(cpus) = &self.cpu_affinity {
+            sched_setaffinity(Pid::from_raw(0), cpus)?;
+        }
+        if let Some(credentials) = &self.credentials {
+            setgroups(&credentials.groups)?;
+            setgid(credentials.gid)?;
+            setuid(credentials.uid)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+use super::jobconfig::rolling::Rolling;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Restart of processes in batches, each batch being restarted once the previous one is
+/// running
+#[derive(Debug)]
+pub struct Rollout {
+    /// Indices of the processes left to restart
+    pub queue: VecDeque<usize>,
+    /// Indices of the processes restarted by the current batch
+    pub batch: Vec<usize>,
+    /// Number of processes restarted at once
+    pub size: usize,
+    pub pause: Duration,
+    /// End of the pause after the last batch, before the next one is restarted
+    pub resume_at: Option<Instant>,
+}
+
+impl Rollout {
+    pub fn new(rolling: Rolling) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            batch: Vec::new(),
+            size: rolling.batch.get() as usize,
+            pause: Duration::from_secs(rolling.pause),
+            resume_at: None,
+        }
+    }
+
+    /// Add a process to restart, unless it is already part of the rollout
+    pub fn push(&mut self, index: usize) {
+        if !self.queue.contains(&index) && !self.batch.contains(&index) {
+            self.queue.push_back(index);
+        }
+    }
+}
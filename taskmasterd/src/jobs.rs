@@ -1,12 +1,59 @@
+use crate::configdiff;
+use crate::job::jobconfig::dependson::Condition;
+use crate::job::jobconfig::numprocs::NumProcs;
+use crate::job::process::output::Stream;
+use crate::job::process::Process;
 use crate::job::{find_config, Job};
-use anyhow::{anyhow, Context, Result};
+use crate::jobs::group::GroupConfig;
+use crate::logger;
+use crate::logger::config::LoggerConfig;
+use crate::procfs;
+use crate::selector::Selector;
+use crate::statefile::SavedState;
+use crate::timers::Timers;
+use crate::upgrade::Handoff;
+use anyhow::{bail, Context, Result};
+use log::{error, info, warn};
+use nix::errno::Errno;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{getpid, Pid};
 use serde::Deserialize;
-use std::collections::HashMap;
+use serde_yaml::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::num::NonZeroU32;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Instant;
+use taskmasterproto::{
+    ConfigDiff, Health, Outcome, ProcessResult, ProcessState, ProgramStatus, TargetError,
+    TargetResult,
+};
+
+pub mod group;
 
 #[derive(Debug, Deserialize, Default)]
 pub struct Jobs {
     pub programs: HashMap<String, Job>,
+    #[serde(default)]
+    pub groups: BTreeMap<String, GroupConfig>,
+    #[serde(default)]
+    pub log: LoggerConfig,
+    /// Content of the config file the jobs were loaded from
+    #[serde(skip)]
+    source: Option<String>,
+    /// Path of the config file the jobs were loaded from
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// Programs being stopped by an update, with the version replacing them once stopped,
+    /// `None` if they are removed
+    #[serde(skip)]
+    pending: HashMap<String, Option<Job>>,
+    #[serde(skip)]
+    timers: Timers,
+    /// Output pipes watched by the main loop, with the program and index of their process
+    #[serde(skip)]
+    outputs: HashMap<RawFd, (String, usize)>,
 }
 
 impl Jobs {
@@ -15,178 +62,1296 @@ impl Jobs {
             Some(path) => load_config_file(path).context("Failed to load config file")?,
             None => Jobs::default(),
         };
+        logger::configure(&jobs.log)?;
         Ok(jobs)
     }
 
-    pub fn load_new_jobs(&mut self, new_jobs: Jobs) -> Result<()> {
-        // if job is in new config, and is not equal to old config, update it and restart it
-        // if job is in new config but not in old config, insert it
-        // if job is in old config but not in new config, remove it
-        let to_remove = self
+    /// Programs of `new_jobs` that are not in the running config, differ from it, or are
+    /// missing from `new_jobs`
+    ///
+    /// Programs being replaced by an update are compared with their replacement.
+    pub fn diff(&self, new_jobs: &Jobs) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+        for (name, job) in new_jobs.programs.iter() {
+            match self.current(name) {
+                None => diff.added.push(name.clone()),
+                Some(current) if current != job => diff.changed.push(name.clone()),
+                Some(_) => {}
+            }
+        }
+        let names = self.programs.keys().chain(self.pending.keys());
+        for name in names.collect::<BTreeSet<_>>() {
+            if self.current(name).is_some() && !new_jobs.programs.contains_key(name) {
+                diff.removed.push(name.clone());
+            }
+        }
+        diff.added.sort_unstable();
+        diff.changed.sort_unstable();
+        for (name, group) in new_jobs.groups.iter() {
+            match self.groups.get(name) {
+                None => diff.added_groups.push(name.clone()),
+                Some(current) if current != group => diff.changed_groups.push(name.clone()),
+                Some(_) => {}
+            }
+        }
+        for name in self.groups.keys() {
+            if !new_jobs.groups.contains_key(name) {
+                diff.removed_groups.push(name.clone());
+            }
+        }
+        let programs = |source: &Option<String>| {
+            let config = serde_yaml::from_str::<Value>(source.as_deref()?).ok()?;
+            config.get("programs").cloned()
+        };
+        let old_programs = programs(&self.source);
+        let new_programs = programs(&new_jobs.source);
+        for name in diff.changed.iter() {
+            let current = self.current(name).expect("changed program");
+            let job = &new_jobs.programs[name];
+            // numprocs is compared on the jobs as it changes with scaling
+            let yaml = |programs: &Option<Value>| {
+                let mut config = programs.as_ref()?.get(name)?.clone();
+                config.as_mapping_mut()?.remove("numprocs");
+                Some(config)
+            };
+            let mut fields = match (yaml(&old_programs), yaml(&new_programs)) {
+                (Some(old), Some(new)) => configdiff::fields(&old, &new),
+                _ => Vec::new(),
+            };
+            if current.config.numprocs != job.config.numprocs {
+                fields.push(format!(
+                    "numprocs {} -> {}",
+                    current.config.numprocs.0, job.config.numprocs.0
+                ));
+                fields.sort_unstable();
+            }
+            diff.fields.insert(name.clone(), fields);
+        }
+        let running = |job: &Job, from: usize| {
+            job.processes
+                .iter()
+                .skip(from)
+                .filter(|process| process.is_running())
+                .map(|process| process.name.clone())
+                .collect::<Vec<_>>()
+        };
+        for name in diff.changed.iter().chain(diff.removed.iter()) {
+            let Some(job) = self.programs.get(name) else {
+                continue;
+            };
+            match new_jobs.programs.get(name) {
+                Some(new_job) if !self.pending.contains_key(name) && job.only_scales(new_job) => {
+                    let count = new_job.config.numprocs.0.get() as usize;
+                    diff.stop.extend(running(job, count));
+                }
+                Some(_) => diff.restart.extend(running(job, 0)),
+                None => diff.stop.extend(running(job, 0)),
+            }
+        }
+        diff
+    }
+
+    /// The version of a program the running config ends up with once updates are done
+    fn current(&self, name: &str) -> Option<&Job> {
+        match self.pending.get(name) {
+            Some(replacement) => replacement.as_ref(),
+            None => self.programs.get(name),
+        }
+    }
+
+    /// Apply the changes of `new_jobs` without waiting for any process
+    ///
+    /// Added programs are started right away. Changed and removed programs are stopped, and
+    /// replaced or removed by `apply_pending` once stopped. The others keep their state.
+    /// Programs join and leave groups along with the version of the program that stays, so a
+    /// removed group lasts until its members are removed.
+    pub fn update(&mut self, mut new_jobs: Jobs) -> Result<ConfigDiff> {
+        let diff = self.diff(&new_jobs);
+        info!(
+            "Updating config: {} added, {} changed, {} removed",
+            diff.added.len(),
+            diff.changed.len(),
+            diff.removed.len()
+        );
+        logger::configure(&new_jobs.log)?;
+        self.log = std::mem::take(&mut new_jobs.log);
+        self.source = new_jobs.source.take();
+        self.path = new_jobs.path.take();
+        self.groups = std::mem::take(&mut new_jobs.groups);
+        for name in diff.changed.iter().chain(diff.added.iter()) {
+            let (name, job) = new_jobs
+                .programs
+                .remove_entry(name)
+                .expect("diffed program");
+            match self.programs.get(&name) {
+                Some(current) if !self.pending.contains_key(&name) && current.only_scales(&job) => {
+                    self.scale_program(&name, job.config.numprocs);
+                }
+                _ => {
+                    self.pending.insert(name, Some(job));
+                }
+            }
+        }
+        for name in diff.removed.iter() {
+            self.pending.insert(name.clone(), None);
+        }
+        self.assign_groups();
+        for name in diff.changed.iter().chain(diff.removed.iter()) {
+            if !self.pending.contains_key(name) {
+                continue;
+            }
+            if let Some(job) = self.programs.get_mut(name) {
+                job.stop();
+            }
+            self.schedule(name);
+        }
+        self.apply_pending();
+        Ok(diff)
+    }
+
+    /// Replace or remove the programs of an update once they are stopped, starting the new
+    /// versions if they are autostarted
+    pub fn apply_pending(&mut self) {
+        let mut stopped = self
+            .pending
+            .iter()
+            .filter(|(name, _)| !self.programs.get(*name).is_some_and(Job::is_running))
+            .map(|(name, job)| (job.as_ref().map(|job| job.config.priority), name.clone()))
+            .collect::<Vec<_>>();
+        // the new versions start by priority, and after their dependencies
+        stopped.sort_unstable();
+        for (_, name) in stopped {
+            self.programs.remove(&name);
+            if let Some(job) = self.pending.remove(&name).flatten() {
+                let autostart = job.config.autostart;
+                self.programs.insert(name.clone(), job);
+                if autostart {
+                    self.start_program(&name);
+                }
+            }
+            self.schedule(&name);
+        }
+    }
+
+    /// Restart the next batch of the rolling restarts whose current batch is running
+    pub fn advance_rollouts(&mut self) {
+        let rolling = self
             .programs
             .iter()
-            .filter(|(name, jobs)| {
-                !new_jobs.programs.contains_key(*name) || *jobs != &new_jobs.programs[*name]
-            })
+            .filter(|(_, job)| job.rollout.is_some())
             .map(|(name, _)| name.clone())
-            .collect::<Vec<String>>();
+            .collect::<Vec<_>>();
+        for name in rolling {
+            if let Some(job) = self.programs.get_mut(&name) {
+                job.advance_rollout(&name);
+            }
+            self.schedule(&name);
+        }
+    }
+
+    /// Whether some programs of an update are still stopping
+    pub fn is_updating(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Whether a rolling restart has batches left to restart
+    pub fn is_rolling(&self) -> bool {
+        self.programs.values().any(|job| job.rollout.is_some())
+    }
+
+    /// Whether processes wait for their dependencies to start, or for their dependents
+    /// to stop
+    pub fn is_queued(&self) -> bool {
+        self.programs
+            .values()
+            .any(|job| !job.queued_starts.is_empty() || !job.queued_stops.is_empty())
+    }
+
+    /// Read the config file the jobs were loaded from again, or the default one
+    pub fn read_config(&self) -> Result<Jobs> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => find_config().context("Failed to find config")?,
+        };
+        load_config_file(path).context("Failed to load config")
+    }
+
+    fn auto_start(&mut self) {
+        for name in self.start_order() {
+            if self.programs[&name].config.autostart {
+                self.start_program(&name);
+            }
+        }
+        self.schedule_all();
+    }
 
-        let to_add = new_jobs
+    /// Program names in the order they start, each one after its dependencies, and by
+    /// priority then name otherwise
+    ///
+    /// Programs in a dependency cycle, or depending on one, are left out.
+    fn start_order(&self) -> Vec<String> {
+        let mut waiting = HashMap::new();
+        let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+        let mut ready = BTreeSet::new();
+        for (name, job) in self.programs.iter() {
+            let dependencies = &job.config.depends_on.0;
+            for dependency in dependencies.keys() {
+                dependents.entry(dependency).or_default().push(name);
+            }
+            if dependencies.is_empty() {
+                ready.insert((job.config.priority, name.as_str()));
+            } else {
+                waiting.insert(name.as_str(), dependencies.len());
+            }
+        }
+        let mut order = Vec::new();
+        while let Some((_, name)) = ready.pop_first() {
+            for dependent in dependents.get(name).into_iter().flatten() {
+                let count = waiting
+                    .get_mut(dependent)
+                    .expect("dependent should be waiting");
+                *count -= 1;
+                if *count == 0 {
+                    ready.insert((self.programs[*dependent].config.priority, *dependent));
+                }
+            }
+            order.push(name.to_string());
+        }
+        order
+    }
+
+    /// Position of each program in the start order
+    fn ranks(&self) -> HashMap<String, usize> {
+        let mut ranks = self
             .programs
-            .into_iter()
-            .filter(|(name, jobs)| {
-                !self.programs.contains_key(name) || self.programs[name] != *jobs
-            })
-            .collect::<Vec<(String, Job)>>();
+            .keys()
+            .map(|name| (name.clone(), usize::MAX))
+            .collect::<HashMap<_, _>>();
+        for (rank, name) in self.start_order().into_iter().enumerate() {
+            ranks.insert(name, rank);
+        }
+        ranks
+    }
 
-        // FIXME This is slow
-        for name in to_remove {
-            self.remove_job(&name)?;
+    /// Reject dependencies on unknown programs, on the health of programs without health
+    /// checks, and dependency cycles
+    fn check_dependencies(&self) -> Result<()> {
+        for (name, job) in self.programs.iter() {
+            for (dependency, condition) in job.config.depends_on.0.iter() {
+                match self.programs.get(dependency) {
+                    None => bail!("{} depends on unknown program {}", name, dependency),
+                    Some(other)
+                        if *condition == Condition::Healthy
+                            && other.config.healthcheck.is_none() =>
+                    {
+                        bail!(
+                            "{} depends on {} being healthy, but {} has no healthcheck",
+                            name,
+                            dependency,
+                            dependency
+                        )
+                    }
+                    Some(_) => {}
+                }
+            }
         }
-        for (name, job) in to_add {
-            self.programs.insert(name, job);
+        let order = self.start_order();
+        if order.len() < self.programs.len() {
+            let mut cycle = self
+                .programs
+                .keys()
+                .filter(|name| !order.contains(name))
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            cycle.sort_unstable();
+            bail!("Dependency cycle between {}", cycle.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Reject empty groups, groups of unknown programs, and programs in several groups
+    /// Reject the names of programs and groups that targets could not tell apart
+    fn check_names(&self) -> Result<()> {
+        for name in self.programs.keys().chain(self.groups.keys()) {
+            if name == "all" {
+                bail!("all is reserved, it targets every process");
+            }
+            if name.contains([':', '*', '?', '[']) {
+                bail!("{} cannot be targeted, : * ? [ are reserved", name);
+            }
+        }
+        for name in self.programs.keys() {
+            // processes are named after their program and their index
+            let program = name
+                .rsplit_once('-')
+                .filter(|(_, index)| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
+                .map(|(program, _)| program);
+            if let Some(program) = program.filter(|program| self.programs.contains_key(*program)) {
+                bail!("{} is also the name of a process of {}", name, program);
+            }
+            if self.groups.contains_key(name) {
+                bail!("{} is the name of both a program and a group", name);
+            }
         }
-        self.auto_start();
         Ok(())
     }
 
-    pub fn auto_start(&mut self) {
+    fn check_groups(&self) -> Result<()> {
+        let mut groups = HashMap::new();
+        for (group, config) in self.groups.iter() {
+            if config.programs.is_empty() {
+                bail!("Group {} has no program", group);
+            }
+            for name in config.programs.iter() {
+                if !self.programs.contains_key(name) {
+                    bail!("Group {} lists unknown program {}", group, name);
+                }
+                if let Some(other) = groups.insert(name, group) {
+                    bail!("{} is in both groups {} and {}", name, other, group);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the group of the programs that stay and of the versions replacing the others,
+    /// programs being removed keep the group they had
+    fn assign_groups(&mut self) {
+        let mut groups = HashMap::new();
+        for (group, config) in self.groups.iter() {
+            for name in config.programs.iter() {
+                groups.insert(name.as_str(), group.as_str());
+            }
+        }
+        let group = |name: &str| groups.get(name).map(|group| group.to_string());
+        for (name, job) in self.programs.iter_mut() {
+            if !self.pending.contains_key(name) {
+                job.group = group(name);
+            }
+        }
+        for (name, job) in self.pending.iter_mut() {
+            if let Some(job) = job {
+                job.group = group(name);
+            }
+        }
+    }
+
+    /// Whether the dependencies of `name` meet their condition
+    ///
+    /// Fails with the reason if one of them is stopped or failed, and will not get there.
+    fn dependencies(&self, name: &str) -> Result<bool, String> {
+        let Some(job) = self.programs.get(name) else {
+            return Ok(false);
+        };
+        let mut met = true;
+        for (dependency, condition) in job.config.depends_on.0.iter() {
+            // the dependency is being replaced by an update
+            if self.pending.contains_key(dependency) {
+                met = false;
+                continue;
+            }
+            let Some(other) = self.programs.get(dependency) else {
+                return Err(format!("{} does not exist", dependency));
+            };
+            for (index, process) in other.processes.iter().enumerate() {
+                let status = process.status();
+                match (status.state, condition) {
+                    (ProcessState::Running, Condition::Healthy) => {
+                        met &= status.health == Some(Health::Healthy)
+                    }
+                    (ProcessState::Running, _)
+                    | (ProcessState::Exited, Condition::Started | Condition::Running)
+                    | (ProcessState::Starting, Condition::Started) => {}
+                    (
+                        ProcessState::Starting | ProcessState::Stopping | ProcessState::Backoff,
+                        _,
+                    ) => met = false,
+                    (ProcessState::Stopped, _) if other.queued_starts.contains(&index) => {
+                        met = false
+                    }
+                    _ => return Err(format!("{} is {}", status.name, status.description)),
+                }
+            }
+        }
+        Ok(met)
+    }
+
+    /// Start the processes of `name` that are not running, after its dependencies
+    fn start_program(&mut self, name: &str) {
+        let stopped = self.programs[name]
+            .processes
+            .iter()
+            .enumerate()
+            .filter(|(_, process)| !process.is_running())
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        for index in stopped {
+            self.start_process(name, index);
+        }
+    }
+
+    /// Start a process once the dependencies of its program meet their condition, starting
+    /// the dependencies that are not running
+    fn start_process(&mut self, name: &str, index: usize) -> Outcome {
+        self.start_dependencies(name, &mut HashSet::new());
+        self.start_or_queue(name, index)
+    }
+
+    /// Start the processes of the dependencies of `name` that are not running, and their
+    /// own dependencies first
+    fn start_dependencies(&mut self, name: &str, visited: &mut HashSet<String>) {
+        let dependencies = self.programs[name]
+            .config
+            .depends_on
+            .0
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for dependency in dependencies {
+            if !self.programs.contains_key(&dependency) || !visited.insert(dependency.clone()) {
+                continue;
+            }
+            self.start_dependencies(&dependency, visited);
+            let job = &self.programs[&dependency];
+            let stopped = (0..job.processes.len())
+                .filter(|i| !job.processes[*i].is_running() && !job.queued_starts.contains(i))
+                .collect::<Vec<_>>();
+            if stopped.is_empty() {
+                continue;
+            }
+            info!(program = dependency.as_str(); "starting as a dependency of {}", name);
+            for index in stopped {
+                self.start_or_queue(&dependency, index);
+            }
+            self.schedule(&dependency);
+        }
+    }
+
+    fn start_or_queue(&mut self, name: &str, index: usize) -> Outcome {
+        let ready = self.dependencies(name);
+        let job = self
+            .programs
+            .get_mut(name)
+            .expect("started job should exist");
+        match ready {
+            Ok(true) => job.start_process(index),
+            Ok(false) => job.queue_start(index),
+            Err(reason) => Outcome::Error {
+                reason: format!("dependency {}", reason),
+            },
+        }
+    }
+
+    /// Start the queued processes whose dependencies meet their condition, and stop the
+    /// queued processes whose dependents are stopped
+    ///
+    /// The starts are given up, and the processes marked FATAL, if a dependency failed.
+    pub fn advance_dependencies(&mut self) {
+        let queued = self
+            .programs
+            .iter()
+            .filter(|(_, job)| !job.queued_starts.is_empty() || !job.queued_stops.is_empty())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in queued {
+            let ready = self.dependencies(&name);
+            let stopped = |(program, index): &(String, usize)| {
+                !self
+                    .programs
+                    .get(program)
+                    .and_then(|job| job.processes.get(*index))
+                    .is_some_and(Process::is_running)
+            };
+            let job = &self.programs[&name];
+            let to_stop = job
+                .queued_stops
+                .iter()
+                .filter(|(_, dependents)| dependents.iter().all(stopped))
+                .map(|(index, _)| *index)
+                .collect::<Vec<_>>();
+            let job = self
+                .programs
+                .get_mut(&name)
+                .expect("queued job should exist");
+            match ready {
+                Ok(true) => {
+                    for index in std::mem::take(&mut job.queued_starts) {
+                        job.start_process(index);
+                    }
+                }
+                Ok(false) => {}
+                Err(reason) => {
+                    for index in std::mem::take(&mut job.queued_starts) {
+                        let process = &mut job.processes[index];
+                        error!(process = process.name.as_str(), transition = "FATAL"; "dependency {}", reason);
+                        process.give_up();
+                    }
+                }
+            }
+            for index in to_stop {
+                if let Outcome::Error { reason } = job.stop_process(index) {
+                    error!(process = job.processes[index].name.as_str(); "failed to stop: {}", reason);
+                }
+            }
+            self.schedule(&name);
+        }
+    }
+
+    /// Describe the jobs for the next image of the daemon
+    pub fn hand_off(&self, listener: RawFd) -> Handoff {
+        Handoff {
+            listener,
+            config: self.source.clone(),
+            path: self.path.clone(),
+            numprocs: self
+                .programs
+                .iter()
+                .map(|(name, job)| (name.clone(), job.config.numprocs.0.get()))
+                .collect(),
+            processes: self
+                .programs
+                .values()
+                .flat_map(|job| job.processes.iter())
+                .map(|process| (process.name.clone(), process.hand_off()))
+                .collect(),
+        }
+    }
+
+    /// Give the programs the number of processes they had in the previous daemon, along with
+    /// the processes past it that it left stopping after scaling down, as told by `exists`
+    fn resize(&mut self, numprocs: &BTreeMap<String, u32>, exists: impl Fn(&str) -> bool) {
+        for (name, job) in self.programs.iter_mut() {
+            if let Some(numprocs) = numprocs.get(name).copied().and_then(NonZeroU32::new) {
+                job.config.numprocs = NumProcs(numprocs);
+            }
+            while exists(&format!("{}-{}", name, job.processes.len()))
+                || job.processes.len() < job.config.numprocs.0.get() as usize
+            {
+                job.add_process(name);
+            }
+        }
+    }
+
+    /// Load the jobs handed over by the previous image of the daemon, from the same config
+    pub fn take_over(mut handoff: Handoff) -> Result<Jobs> {
+        let mut jobs = match &handoff.config {
+            Some(config) => {
+                parse_config(config).context("Failed to load the handed over config")?
+            }
+            None => Jobs::default(),
+        };
+        jobs.path = handoff.path.take();
+        logger::configure(&jobs.log)?;
+        jobs.resize(&handoff.numprocs, |name| {
+            handoff.processes.contains_key(name)
+        });
+        for job in jobs.programs.values_mut() {
+            for process in job.processes.iter_mut() {
+                if let Some(process_handoff) = handoff.processes.remove(&process.name) {
+                    process.take_over(process_handoff)?;
+                }
+            }
+            job.prune();
+        }
+        jobs.schedule_all();
+        Ok(jobs)
+    }
+
+    /// Adopt the processes a previous daemon left running, then start the autostarted
+    /// processes that are not, in the order of their dependencies
+    pub fn restore(&mut self, state: &SavedState) {
+        self.resize(&state.numprocs, |name| state.processes.contains_key(name));
+        let mut unknown = state.processes.keys().collect::<HashSet<_>>();
         for job in self.programs.values_mut() {
-            if job.config.autostart && !job.is_running() {
-                job.start();
+            for process in job.processes.iter_mut() {
+                if let Some(saved) = state.processes.get(&process.name) {
+                    unknown.remove(&process.name);
+                    process.resume(saved);
+                }
             }
+            job.prune();
+        }
+        for name in unknown {
+            warn!(
+                process = name.as_str(), pid = state.processes[name].pid;
+                "left running by a previous daemon, but no longer in the config"
+            );
         }
+        self.auto_start();
     }
 
-    pub fn status(&self, name: &str) -> Result<String> {
-        if name.is_empty() {
-            Ok(self.status_all())
-        } else if let Some(job) = self.programs.get(name) {
-            Ok(job.print_status())
-        } else {
-            Err(anyhow!("Job {} not found", name))
+    /// State of the running processes, for a later daemon to adopt them
+    pub fn saved_state(&self) -> SavedState {
+        SavedState {
+            numprocs: self
+                .programs
+                .iter()
+                .map(|(name, job)| (name.clone(), job.config.numprocs.0.get()))
+                .collect(),
+            processes: self
+                .programs
+                .values()
+                .flat_map(|job| job.processes.iter())
+                .filter_map(|process| Some((process.name.clone(), process.saved()?)))
+                .collect(),
         }
     }
 
-    pub fn status_all(&self) -> String {
-        let mut status = String::new();
+    /// Status of the processes selected by `targets`, and the targets that matched nothing
+    pub fn status(&self, targets: &[String]) -> (Vec<ProgramStatus>, Vec<String>) {
+        let mut selected: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut not_found = Vec::new();
+        for target in all_if_empty(targets) {
+            match self.select(target) {
+                Ok(processes) if !processes.is_empty() => {
+                    for (name, index) in processes {
+                        let indices = selected.entry(name).or_default();
+                        if !indices.contains(&index) {
+                            indices.push(index);
+                        }
+                    }
+                }
+                _ => not_found.push(target.to_string()),
+            }
+        }
+        let ranks = self.ranks();
+        // the members of a group are listed together, where its first member starts
+        let mut group_ranks: HashMap<&str, usize> = HashMap::new();
         for (name, job) in self.programs.iter() {
-            status.push_str(format!("Job status {}:\n", name).as_str());
-            status.push_str(&job.print_status());
-            status.push('\n');
+            if let Some(group) = &job.group {
+                let rank = group_ranks.entry(group).or_insert(usize::MAX);
+                *rank = (*rank).min(ranks[name]);
+            }
         }
-        status
+        let mut selected = selected.into_iter().collect::<Vec<_>>();
+        selected.sort_unstable_by_key(|(name, _)| {
+            let group = self.programs[*name].group.as_deref();
+            let rank = group.map_or(ranks[*name], |group| group_ranks[group]);
+            (rank, group, ranks[*name], *name)
+        });
+        let programs = selected
+            .into_iter()
+            .map(|(name, mut indices)| {
+                indices.sort_unstable();
+                self.programs[name].status(name, &indices)
+            })
+            .collect();
+        (programs, not_found)
     }
 
     pub fn init(&mut self) -> Result<()> {
         self.programs
             .iter_mut()
             .try_for_each(|(name, job)| job.init(name))?;
+        self.check_names()?;
+        self.check_dependencies()?;
+        self.check_groups()?;
+        self.assign_groups();
         Ok(())
     }
 
-    pub fn start(&mut self, name: &str) {
-        if name.is_empty() {
-            return self.start_all();
-        }
-        if let Some(job) = self.programs.get_mut(name) {
-            job.start();
+    /// Resolve a target to the processes it selects, as program names and process indices
+    pub fn select(&self, target: &str) -> Result<Vec<(&str, usize)>> {
+        let selector = Selector::from_str(target)?;
+        let mut selected = Vec::new();
+        for (name, job) in self.programs.iter() {
+            let group = job.group.as_deref();
+            let whole = selector.matches_program(name, group);
+            for (index, process) in job.processes.iter().enumerate() {
+                if whole || selector.matches_process(&process.name, group) {
+                    selected.push((name.as_str(), index));
+                }
+            }
         }
+        let ranks = self.ranks();
+        selected.sort_unstable_by_key(|(name, index)| (ranks[*name], *name, *index));
+        Ok(selected)
     }
 
-    pub fn start_all(&mut self) {
-        for job in self.programs.values_mut() {
-            job.start();
+    /// Apply `action` to the processes selected by each target
+    pub fn apply<F>(&mut self, targets: &[String], mut action: F) -> Vec<TargetResult>
+    where
+        F: FnMut(&mut Job, usize) -> Outcome,
+    {
+        self.apply_named(targets, |jobs, name, index| {
+            let job = jobs
+                .programs
+                .get_mut(name)
+                .expect("selected job should exist");
+            action(job, index)
+        })
+    }
+
+    /// Start the processes selected by `targets` once the dependencies of their programs
+    /// meet their condition, starting the dependencies that are not running
+    pub fn start(&mut self, targets: &[String]) -> Vec<TargetResult> {
+        self.apply_named(targets, |jobs, name, index| jobs.start_process(name, index))
+    }
+
+    /// Stop the processes selected by `targets`, each one once the selected processes of
+    /// the programs depending on it, or starting after it with a higher priority, are stopped
+    pub fn stop(&mut self, targets: &[String]) -> Vec<TargetResult> {
+        let ranks = self.ranks();
+        let selected = all_if_empty(targets)
+            .into_iter()
+            .filter_map(|target| self.select(target).ok())
+            .flatten()
+            .map(|(name, index)| (name.to_string(), index))
+            .collect::<BTreeSet<_>>();
+        self.apply_named(targets, |jobs, name, index| {
+            let dependents = selected
+                .iter()
+                .filter(|(program, i)| {
+                    let job = &jobs.programs[program];
+                    // following the start order, which cannot contradict itself
+                    ranks[program] > ranks[name]
+                        && (job.config.depends_on.0.contains_key(name)
+                            || job.config.priority > jobs.programs[name].config.priority)
+                        && job.processes[*i].is_running()
+                })
+                .cloned()
+                .collect::<BTreeSet<_>>();
+            let job = jobs
+                .programs
+                .get_mut(name)
+                .expect("selected job should exist");
+            if dependents.is_empty() || !job.processes[index].is_running() {
+                job.stop_process(index)
+            } else {
+                job.queue_stop(index, dependents)
+            }
+        })
+    }
+
+    /// Apply `action` to the processes selected by each target, as program names and
+    /// process indices, with access to the other jobs
+    fn apply_named<F>(&mut self, targets: &[String], mut action: F) -> Vec<TargetResult>
+    where
+        F: FnMut(&mut Self, &str, usize) -> Outcome,
+    {
+        let mut results = Vec::new();
+        for target in all_if_empty(targets) {
+            let selected = match self.select(target) {
+                Ok(selected) => selected
+                    .into_iter()
+                    .map(|(name, index)| (name.to_string(), index))
+                    .collect::<Vec<_>>(),
+                Err(e) => {
+                    results.push(TargetResult {
+                        target: target.to_string(),
+                        processes: Vec::new(),
+                        error: Some(TargetError::Invalid {
+                            reason: format!("{:#}", e),
+                        }),
+                    });
+                    continue;
+                }
+            };
+            let mut processes = Vec::new();
+            for (name, index) in selected {
+                let outcome = action(self, &name, index);
+                processes.push(ProcessResult {
+                    outcome,
+                    name: self.programs[&name].processes[index].name.clone(),
+                });
+                self.schedule(&name);
+            }
+            let error = processes.is_empty().then_some(TargetError::NotFound);
+            results.push(TargetResult {
+                target: target.to_string(),
+                processes,
+                error,
+            });
         }
+        results
     }
 
-    pub fn stop(&mut self, name: &str) -> Result<()> {
-        if name.is_empty() {
-            return self.stop_all();
+    /// Set the number of processes of `program`, only starting or stopping the difference
+    pub fn scale(&mut self, program: &str, numprocs: u32) -> TargetResult {
+        let mut result = TargetResult {
+            target: program.to_string(),
+            processes: Vec::new(),
+            error: None,
+        };
+        let Some(numprocs) = NonZeroU32::new(numprocs) else {
+            result.error = Some(TargetError::Invalid {
+                reason: "numprocs must be at least 1".to_string(),
+            });
+            return result;
+        };
+        if self.pending.contains_key(program) {
+            result.error = Some(TargetError::Invalid {
+                reason: "the program is being updated".to_string(),
+            });
+            return result;
         }
-        if let Some(job) = self.programs.get_mut(name) {
-            job.stop()?;
+        if !self.programs.contains_key(program) {
+            result.error = Some(TargetError::NotFound);
+            return result;
         }
-        Ok(())
+        info!(program = program; "scaling to {} processes", numprocs);
+        result.processes = self.scale_program(program, NumProcs(numprocs));
+        result
     }
 
-    pub fn stop_all(&mut self) -> Result<()> {
+    /// Set the number of processes of `name`, the new ones are started after the
+    /// dependencies of the program if it is up
+    fn scale_program(&mut self, name: &str, numprocs: NumProcs) -> Vec<ProcessResult> {
+        let job = self
+            .programs
+            .get_mut(name)
+            .expect("scaled job should exist");
+        let up = job.is_up();
+        let added = job.processes.len()..numprocs.0.get() as usize;
+        let mut results = job.scale(name, numprocs);
+        for index in added {
+            let outcome = match up {
+                true => self.start_process(name, index),
+                false => Outcome::Ok,
+            };
+            results.push(ProcessResult {
+                name: self.programs[name].processes[index].name.clone(),
+                outcome,
+            });
+        }
+        self.schedule(name);
+        results
+    }
+
+    /// Stop every process, the programs depending on others first
+    pub fn stop_all(&mut self) {
+        self.adopt_orphans();
         for job in self.programs.values_mut() {
-            job.stop()?;
+            job.rollout = None;
+            job.queued_starts.clear();
         }
-        Ok(())
+        let results = self.stop(&[]);
+        for process in results.iter().flat_map(|result| result.processes.iter()) {
+            if let Outcome::Error { reason } = &process.outcome {
+                error!(process = process.name.as_str(); "failed to stop: {}", reason);
+            }
+        }
+        self.schedule_all();
     }
 
-    pub fn restart(&mut self, name: &str) -> Result<()> {
-        if name.is_empty() {
-            return self.restart_all();
+    pub fn is_running(&self) -> bool {
+        self.programs.values().any(Job::is_running)
+    }
+
+    /// Reap every exited child and update the matching processes
+    pub fn reap_children(&mut self) {
+        loop {
+            let (pid, code) = match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::Exited(pid, code)) => (pid, Some(code)),
+                Ok(WaitStatus::Signaled(pid, _, _)) => (pid, None),
+                Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => break,
+                Ok(_) => continue,
+                Err(e) => {
+                    error!("Failed to wait for children: {}", e);
+                    break;
+                }
+            };
+            self.reap(pid, code);
         }
-        if let Some(job) = self.programs.get_mut(name) {
-            job.restart()?;
+        // the children of the processes that exited were reparented to the daemon
+        self.adopt_orphans();
+    }
+
+    /// Output pipes of the processes started since the last call, for the main loop to watch
+    pub fn register_outputs(&mut self) -> Vec<RawFd> {
+        let mut registered = Vec::new();
+        for (name, job) in self.programs.iter_mut() {
+            for (index, process) in job.processes.iter_mut().enumerate() {
+                for pipe in process.unregistered_outputs() {
+                    pipe.registered = true;
+                    self.outputs.insert(pipe.as_raw_fd(), (name.clone(), index));
+                    registered.push(pipe.as_raw_fd());
+                }
+            }
         }
-        Ok(())
+        registered
     }
 
-    pub fn restart_all(&mut self) -> Result<()> {
-        for job in self.programs.values_mut() {
-            job.restart()?;
+    /// Pidfds of the processes adopted since the last call, for the main loop to watch
+    pub fn register_pidfds(&mut self) -> Vec<RawFd> {
+        self.programs
+            .values_mut()
+            .flat_map(|job| job.processes.iter_mut())
+            .filter_map(Process::unregistered_pidfd)
+            .collect()
+    }
+
+    /// Notify sockets of the processes started since the last call, for the main loop to watch
+    pub fn register_notify_sockets(&mut self) -> Vec<RawFd> {
+        self.programs
+            .values_mut()
+            .flat_map(|job| job.processes.iter_mut())
+            .filter_map(Process::unregistered_notify)
+            .collect()
+    }
+
+    /// Connections of health checks that are not watched by the main loop yet
+    pub fn register_probes(&mut self) -> Vec<RawFd> {
+        self.programs
+            .values_mut()
+            .flat_map(|job| job.processes.iter_mut())
+            .filter_map(Process::unregistered_probe)
+            .collect()
+    }
+
+    /// Go on with the health check whose connection is `fd`
+    pub fn poll_probe(&mut self, fd: RawFd) {
+        let name = self.programs.iter_mut().find_map(|(name, job)| {
+            let process = job.processes.iter_mut().find(|p| p.has_probe(fd))?;
+            process.check_health(Instant::now());
+            Some(name.clone())
+        });
+        if let Some(name) = name {
+            self.schedule(&name);
         }
-        Ok(())
     }
 
-    pub fn check_status(&mut self) -> Result<()> {
-        for job in self.programs.values_mut() {
-            job.check_status()?;
+    /// Handle the notifications sent to the notify socket `fd`
+    pub fn read_notifications(&mut self, fd: RawFd) {
+        let name = self.programs.iter_mut().find_map(|(name, job)| {
+            let process = job.processes.iter_mut().find(|p| p.has_notify(fd))?;
+            process.read_notifications();
+            Some(name.clone())
+        });
+        if let Some(name) = name {
+            self.schedule(&name);
         }
-        Ok(())
     }
 
-    pub fn reload(&mut self) -> Result<()> {
-        println!("Reloading config");
-        self.stop_all()?;
-        self.try_wait_job_stop()?;
-        self.clear_jobs();
-        *self = Jobs::new().context("Failed to load new config")?;
-        self.auto_start();
-        Ok(())
+    /// Handle the exit of the adopted process whose pidfd is `fd`
+    pub fn reap_adopted(&mut self, fd: RawFd) {
+        let pid = self
+            .programs
+            .values()
+            .flat_map(|job| job.processes.iter())
+            .find(|process| process.has_pidfd(fd))
+            .and_then(Process::pid);
+        if let Some(pid) = pid {
+            self.reap(pid, None);
+        }
     }
 
-    pub fn reread(&mut self) -> Result<()> {
-        println!("Rereading config");
-        self.stop_all()?;
-        let path = find_config().context("Failed to find config")?;
-        let new_jobs = load_config_file(path).context("Failed to load config")?;
-        self.try_wait_job_stop()?;
-        *self = new_jobs;
-        self.auto_start();
-        Ok(())
+    /// Read the output a process wrote to the pipe `fd`
+    ///
+    /// Returns the name of the process, the stream and the data read.
+    pub fn read_output(&mut self, fd: RawFd) -> Option<(&str, Stream, Vec<u8>)> {
+        let (name, index) = self.outputs.get(&fd)?;
+        let process = self
+            .programs
+            .get_mut(name)
+            .and_then(|job| job.processes.get_mut(*index))?;
+        match process.read_output(fd) {
+            Some((stream, data)) => Some((&process.name, stream, data)),
+            None => {
+                self.outputs.remove(&fd);
+                None
+            }
+        }
+    }
+
+    /// Write `data` to the stdin of the process `name`
+    pub fn write_input(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        let process = self
+            .programs
+            .values_mut()
+            .flat_map(|job| job.processes.iter_mut())
+            .find(|process| process.name == name)
+            .with_context(|| format!("{} does not exist anymore", name))?;
+        process.write_input(data)
     }
 
-    fn try_wait_job_stop(&mut self) -> Result<()> {
-        while self.programs.values().any(Job::is_running) {
-            self.check_status()?;
-            std::thread::sleep(std::time::Duration::from_millis(100));
+    /// Attribute the descendants reparented to the daemon to the process whose session or
+    /// process group they belong to
+    pub fn adopt_orphans(&mut self) {
+        let children = match procfs::children(getpid()) {
+            Ok(children) => children,
+            Err(e) => {
+                error!("Failed to look for orphans: {:#}", e);
+                return;
+            }
+        };
+        let leaders: HashSet<Pid> = self
+            .programs
+            .values()
+            .flat_map(|job| job.processes.iter().filter_map(Process::pid))
+            .collect();
+        let orphans = children
+            .into_iter()
+            .filter(|pid| !leaders.contains(pid))
+            .filter_map(procfs::stat)
+            .collect::<Vec<_>>();
+        for (name, job) in self.programs.iter_mut() {
+            let mut adopted = false;
+            for process in job.processes.iter_mut() {
+                process.prune_sessions();
+                let owned = orphans
+                    .iter()
+                    .filter(|orphan| process.owns(orphan))
+                    .map(|orphan| orphan.pid)
+                    .collect::<Vec<_>>();
+                for pid in owned {
+                    process.adopt(pid);
+                    adopted = true;
+                }
+            }
+            if adopted {
+                self.timers.schedule(name, job.next_deadline());
+            }
         }
-        Ok(())
     }
 
-    pub fn remove_job(&mut self, name: &str) -> Result<()> {
-        if let Some(job) = self.programs.get_mut(name) {
-            job.stop()?;
+    fn reap(&mut self, pid: Pid, code: Option<i32>) {
+        let name = self
+            .programs
+            .iter_mut()
+            .find_map(|(name, job)| job.reap(pid, code).then(|| name.clone()));
+        if let Some(name) = name {
+            if let Some(job) = self.programs.get_mut(&name) {
+                job.prune();
+            }
+            self.schedule(&name);
         }
-        self.try_wait_job_stop()?;
-        self.programs.remove(name);
-        Ok(())
     }
 
-    pub fn clear_jobs(&mut self) {
-        self.programs.clear();
+    /// Handle the jobs whose deadline is reached
+    pub fn check_timers(&mut self) {
+        for name in self.timers.expired(Instant::now()) {
+            if let Some(job) = self.programs.get_mut(&name) {
+                job.check_status();
+                job.prune();
+            }
+            self.schedule(&name);
+        }
+    }
+
+    /// Earliest time at which `check_timers` has something to do
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.next()
+    }
+
+    fn schedule(&mut self, name: &str) {
+        let deadline = self.programs.get(name).and_then(Job::next_deadline);
+        self.timers.schedule(name, deadline);
+    }
+
+    fn schedule_all(&mut self) {
+        for (name, job) in self.programs.iter() {
+            self.timers.schedule(name, job.next_deadline());
+        }
+    }
+}
+
+/// No target means every process
+fn all_if_empty(targets: &[String]) -> Vec<&str> {
+    if targets.is_empty() {
+        vec!["all"]
+    } else {
+        targets.iter().map(String::as_str).collect()
     }
 }
 
 pub fn load_config_file(path: PathBuf) -> Result<Jobs> {
-    let file = std::fs::File::open(path)?;
-    let mut jobs: Jobs = serde_yaml::from_reader(file)?;
+    let mut jobs = parse_config(&std::fs::read_to_string(&path)?)?;
+    jobs.path = Some(path);
+    Ok(jobs)
+}
+
+fn parse_config(config: &str) -> Result<Jobs> {
+    let mut jobs: Jobs = serde_yaml::from_str(config)?;
     jobs.init()?;
+    jobs.source = Some(config.to_string());
     Ok(jobs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let jobs = parse_config(
+            "programs:\n  a:\n    cmd: sleep 1\n  b:\n    cmd: sleep 2\n  c:\n    cmd: sleep 3\n",
+        )
+        .unwrap();
+        let new_jobs = parse_config(
+            "programs:\n  a:\n    cmd: sleep 1\n  b:\n    cmd: sleep 4\n  d:\n    cmd: sleep 3\n",
+        )
+        .unwrap();
+        let diff = jobs.diff(&new_jobs);
+        assert_eq!(diff.added, ["d"]);
+        assert_eq!(diff.changed, ["b"]);
+        assert_eq!(diff.removed, ["c"]);
+        assert!(new_jobs.diff(&new_jobs).is_empty());
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let jobs = parse_config(
+            "programs:\n  app:\n    cmd: sleep 1\n    depends_on: [db, cache]\n  cache:\n    cmd: sleep 1\n  db:\n    cmd: sleep 1\n    depends_on: {disk: started}\n  disk:\n    cmd: sleep 1\n",
+        )
+        .unwrap();
+        assert_eq!(jobs.start_order(), ["cache", "disk", "db", "app"]);
+        let error = |config: &str| format!("{:#}", parse_config(config).unwrap_err());
+        assert_eq!(
+            error("programs:\n  a:\n    cmd: sleep 1\n    depends_on: [b]\n  b:\n    cmd: sleep 1\n    depends_on: [a]\n  c:\n    cmd: sleep 1\n"),
+            "Dependency cycle between a, b"
+        );
+        assert_eq!(
+            error("programs:\n  a:\n    cmd: sleep 1\n    depends_on: [b]\n"),
+            "a depends on unknown program b"
+        );
+        assert_eq!(
+            error("programs:\n  a:\n    cmd: sleep 1\n    depends_on: {b: healthy}\n  b:\n    cmd: sleep 1\n"),
+            "a depends on b being healthy, but b has no healthcheck"
+        );
+    }
+
+    #[test]
+    fn test_priority() {
+        let jobs = parse_config(
+            "programs:\n  a:\n    cmd: sleep 1\n    priority: 5\n  b:\n    cmd: sleep 1\n    priority: -1\n  c:\n    cmd: sleep 1\n  d:\n    cmd: sleep 1\n    priority: 1\n    depends_on: [c]\n",
+        )
+        .unwrap();
+        // dependencies come first whatever their priority
+        assert_eq!(jobs.start_order(), ["b", "a", "c", "d"]);
+        let selected = jobs.select("all").unwrap();
+        let names = selected.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(names, ["b", "a", "c", "d"]);
+    }
+
+    #[test]
+    fn test_kill_gone() {
+        use crate::job::process::{RunningStatus, State};
+        use std::time::Duration;
+
+        let mut jobs =
+            parse_config("programs:\n  a:\n    cmd: sleep 1\n    stoptimeout: 1\n").unwrap();
+        // above pid_max, which is at most 2^22, so no process can have it
+        jobs.programs.get_mut("a").unwrap().processes[0].state = State::Running {
+            pid: Pid::from_raw(i32::MAX),
+            status: RunningStatus::StopRequested {
+                since: Instant::now() - Duration::from_secs(10),
+                restart: false,
+            },
+        };
+        jobs.schedule("a");
+        jobs.check_timers();
+        // a process that is already gone is not killed again
+        assert_eq!(jobs.programs["a"].processes[0].pid(), None);
+        assert_eq!(jobs.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_scale() {
+        use crate::job::process::{RunningStatus, State};
+
+        let mut jobs = parse_config(
+            "programs:\n  app:\n    cmd: sleep 1\n    depends_on: [db]\n  db:\n    cmd: sleep 1\n",
+        )
+        .unwrap();
+        // a stopped program stays stopped, autostarted or not
+        let result = jobs.scale("app", 2);
+        assert_eq!(result.processes[0].outcome, Outcome::Ok);
+        assert!(!jobs.programs["app"].processes[1].is_running());
+        // only placeholders, the pids are never signaled
+        let starting = |pid| State::Running {
+            pid: Pid::from_raw(pid),
+            status: RunningStatus::StartRequested {
+                start: Instant::now(),
+                tries: 0,
+            },
+        };
+        jobs.programs.get_mut("db").unwrap().processes[0].state = starting(i32::MAX);
+        jobs.programs.get_mut("app").unwrap().processes[0].state = starting(i32::MAX - 1);
+        // new processes of a running program wait for its dependencies
+        let result = jobs.scale("app", 3);
+        assert_eq!(result.processes[0].name, "app-2");
+        assert_eq!(result.processes[0].outcome, Outcome::Queued);
+        assert!(jobs.programs["app"].queued_starts.contains(&2));
+        assert!(!jobs.programs["app"].processes[1].is_running());
+    }
+
+    #[test]
+    fn test_names() {
+        let error = |config: &str| format!("{:#}", parse_config(config).unwrap_err());
+        assert_eq!(
+            error("programs:\n  all:\n    cmd: sleep 1\n"),
+            "all is reserved, it targets every process"
+        );
+        assert_eq!(
+            error("programs:\n  web:\n    cmd: sleep 1\n  web-1:\n    cmd: sleep 1\n"),
+            "web-1 is also the name of a process of web"
+        );
+        assert_eq!(
+            error("programs:\n  web:\n    cmd: sleep 1\ngroups:\n  web:\n    programs: [web]\n"),
+            "web is the name of both a program and a group"
+        );
+        assert_eq!(
+            error("programs:\n  a:b:\n    cmd: sleep 1\n"),
+            "a:b cannot be targeted, : * ? [ are reserved"
+        );
+        assert!(parse_config(
+            "programs:\n  web-api:\n    cmd: sleep 1\n  web:\n    cmd: sleep 1\n"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_groups() {
+        let config = "programs:\n  web:\n    cmd: sleep 1\n    numprocs: 2\n  worker:\n    cmd: sleep 1\n    priority: 1\n  db:\n    cmd: sleep 1\n    priority: 500\n";
+        let mut jobs = parse_config(&format!(
+            "{}groups:\n  app:\n    programs: [web, worker]\n",
+            config
+        ))
+        .unwrap();
+        let names = |selected: Vec<(&str, usize)>| {
+            selected
+                .into_iter()
+                .map(|(name, index)| format!("{}-{}", name, index))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(jobs.select("app:").unwrap()),
+            ["worker-0", "web-0", "web-1"]
+        );
+        assert_eq!(names(jobs.select("app:web-1").unwrap()), ["web-1"]);
+        assert!(jobs.select("app:db").unwrap().is_empty());
+        // the members are listed together, where worker starts
+        let (programs, _) = jobs.status(&[]);
+        let order = programs.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(order, ["worker", "web", "db"]);
+        assert_eq!(programs[1].group.as_deref(), Some("app"));
+        assert_eq!(programs[2].group, None);
+        let error = |config: &str| format!("{:#}", parse_config(config).unwrap_err());
+        assert_eq!(
+            error(&format!(
+                "{}groups:\n  app:\n    programs: [web, api]\n",
+                config
+            )),
+            "Group app lists unknown program api"
+        );
+        assert_eq!(
+            error(&format!(
+                "{}groups:\n  a:\n    programs: [web]\n  b:\n    programs: [web]\n",
+                config
+            )),
+            "web is in both groups a and b"
+        );
+        let new_jobs = parse_config(&format!(
+            "{}groups:\n  back:\n    programs: [worker, db]\n",
+            config
+        ))
+        .unwrap();
+        let diff = jobs.update(new_jobs).unwrap();
+        assert_eq!(diff.added_groups, ["back"]);
+        assert_eq!(diff.removed_groups, ["app"]);
+        assert!(diff.changed.is_empty());
+        assert_eq!(names(jobs.select("back:").unwrap()), ["worker-0", "db-0"]);
+        assert!(jobs.select("app:").unwrap().is_empty());
+    }
+}
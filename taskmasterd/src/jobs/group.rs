@@ -0,0 +1,10 @@
+use serde::Deserialize;
+use std::collections::BTreeSet;
+
+/// Entry of the `groups` section of the config file, programs operated on together
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct GroupConfig {
+    /// Member programs, each one in a single group
+    pub programs: BTreeSet<String>,
+}
@@ -0,0 +1,195 @@
+use crate::logger::config::{Format, LoggerConfig};
+use crate::logger::sink::Sink;
+use anyhow::{Context, Result};
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::Value as Json;
+use std::fmt::Write;
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+pub mod config;
+mod sink;
+
+static LOGGER: Logger = Logger {
+    output: Mutex::new(None),
+};
+
+/// Logger of the daemon, records carry fields such as `process` and `transition`
+///
+/// Records are written as text to stderr until the config file is loaded.
+struct Logger {
+    output: Mutex<Option<Output>>,
+}
+
+#[derive(Debug)]
+struct Output {
+    format: Format,
+    sink: Sink,
+}
+
+/// Install the logger
+pub fn init() -> Result<()> {
+    log::set_logger(&LOGGER).context("Failed to install the logger")?;
+    log::set_max_level(LevelFilter::Info);
+    Ok(())
+}
+
+/// Apply the `log` section of a config file
+pub fn configure(config: &LoggerConfig) -> Result<()> {
+    let sink = Sink::open(config).context("Failed to open the log destination")?;
+    *LOGGER.output.lock().unwrap_or_else(PoisonError::into_inner) = Some(Output {
+        format: config.format,
+        sink,
+    });
+    log::set_max_level(config.level.into());
+    Ok(())
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut fields = Fields::default();
+        // collecting fields never fails
+        let _ = record.key_values().visit(&mut fields);
+        let message = record.args().to_string();
+        let mut output = self.output.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut stderr = Sink::Stderr;
+        let (format, sink) = match &mut *output {
+            Some(output) => (output.format, &mut output.sink),
+            None => (Format::Text, &mut stderr),
+        };
+        let result = match sink {
+            Sink::Journald(_) => sink.write_fields(record.level(), &message, &fields.to_strings()),
+            Sink::Syslog(_) => {
+                let line = format_record(format, None, record.level(), &message, &fields.0);
+                sink.write_line(record.level(), &line)
+            }
+            _ => {
+                let timestamp = humantime::format_rfc3339_millis(SystemTime::now()).to_string();
+                let line = format_record(
+                    format,
+                    Some(&timestamp),
+                    record.level(),
+                    &message,
+                    &fields.0,
+                );
+                sink.write_line(record.level(), &line)
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to write log record: {:#}", e);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Key-value pairs of a record
+#[derive(Default)]
+struct Fields(Vec<(String, Json)>);
+
+impl Fields {
+    fn to_strings(&self) -> Vec<(String, String)> {
+        self.0
+            .iter()
+            .map(|(key, value)| match value {
+                Json::String(s) => (key.clone(), s.clone()),
+                value => (key.clone(), value.to_string()),
+            })
+            .collect()
+    }
+}
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = if let Some(n) = value.to_i64() {
+            Json::from(n)
+        } else if let Some(b) = value.to_bool() {
+            Json::from(b)
+        } else {
+            Json::from(value.to_string())
+        };
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+/// Format a record as a line, without the timestamp when the destination adds its own
+fn format_record(
+    format: Format,
+    timestamp: Option<&str>,
+    level: Level,
+    message: &str,
+    fields: &[(String, Json)],
+) -> String {
+    match format {
+        Format::Text => {
+            let mut line = String::new();
+            if let Some(timestamp) = timestamp {
+                line.push_str(timestamp);
+                line.push(' ');
+            }
+            let _ = write!(line, "{:<5} {}", level, message);
+            for (key, value) in fields {
+                match value {
+                    Json::String(s) if !s.is_empty() && !s.contains(char::is_whitespace) => {
+                        let _ = write!(line, " {}={}", key, s);
+                    }
+                    value => {
+                        let _ = write!(line, " {}={}", key, value);
+                    }
+                }
+            }
+            line
+        }
+        Format::Json => {
+            // written by hand to keep the keys in order
+            let mut pairs = Vec::new();
+            if let Some(timestamp) = timestamp {
+                pairs.push(("timestamp", Json::from(timestamp)));
+            }
+            pairs.push(("level", Json::from(level.as_str())));
+            pairs.push(("message", Json::from(message)));
+            pairs.extend(
+                fields
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.clone())),
+            );
+            let pairs = pairs
+                .iter()
+                .map(|(key, value)| format!("{}:{}", Json::from(*key), value))
+                .collect::<Vec<_>>();
+            format!("{{{}}}", pairs.join(","))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_record() {
+        let fields = vec![
+            ("process".to_string(), Json::from("web-0")),
+            ("pid".to_string(), Json::from(42)),
+            ("reason".to_string(), Json::from("no such file")),
+        ];
+        let timestamp = Some("2023-01-01T00:00:00.000Z");
+        assert_eq!(
+            format_record(Format::Text, timestamp, Level::Info, "started", &fields),
+            "2023-01-01T00:00:00.000Z INFO  started process=web-0 pid=42 reason=\"no such file\""
+        );
+        assert_eq!(
+            format_record(Format::Json, None, Level::Warn, "started", &fields[..2]),
+            r#"{"level":"WARN","message":"started","process":"web-0","pid":42}"#
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use crate::job::jobconfig::backups::Backups;
+use crate::job::jobconfig::maxbytes::MaxBytes;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// `log` section of the config file
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct LoggerConfig {
+    #[serde(default)]
+    pub level: Level,
+    #[serde(default)]
+    pub format: Format,
+    #[serde(default)]
+    pub destination: Destination,
+    /// Log file, required by the `file` destination
+    pub file: Option<PathBuf>,
+    #[serde(default)]
+    pub maxbytes: MaxBytes,
+    #[serde(default)]
+    pub backups: Backups,
+    #[serde(default)]
+    pub compress: bool,
+}
+
+/// Most verbose level logged
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<Level> for LevelFilter {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Off => LevelFilter::Off,
+            Level::Error => LevelFilter::Error,
+            Level::Warn => LevelFilter::Warn,
+            Level::Info => LevelFilter::Info,
+            Level::Debug => LevelFilter::Debug,
+            Level::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// How a record is written out
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Timestamp, level and message followed by the fields as `key=value`
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Where the records go
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Destination {
+    /// The stderr of the daemon, `~/.taskmasterd/taskmasterd.stderr` once daemonized
+    #[default]
+    Stderr,
+    /// The rotating file set by `file`
+    File,
+    /// The local syslog daemon, through `/dev/log`
+    Syslog,
+    /// The systemd journal, through its native protocol
+    Journald,
+}
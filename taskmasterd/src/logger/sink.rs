@@ -0,0 +1,145 @@
+use crate::job::jobconfig::LogConfig;
+use crate::job::process::logfile::LogFile;
+use crate::logger::config::{Destination, LoggerConfig};
+use anyhow::{Context, Result};
+use log::Level;
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// Facility of the records sent to syslog
+const LOG_DAEMON: u8 = 3 << 3;
+const IDENTIFIER: &str = "taskmasterd";
+
+/// Destination of the log records
+#[derive(Debug)]
+pub enum Sink {
+    Stderr,
+    File(LogFile),
+    Syslog(UnixDatagram),
+    Journald(UnixDatagram),
+}
+
+impl Sink {
+    pub fn open(config: &LoggerConfig) -> Result<Self> {
+        Ok(match config.destination {
+            Destination::Stderr => Self::Stderr,
+            Destination::File => {
+                let path = config
+                    .file
+                    .as_deref()
+                    .context("The file destination needs a log file")?;
+                Self::File(LogFile::open(&LogConfig {
+                    path,
+                    maxbytes: config.maxbytes.0,
+                    backups: config.backups.0,
+                    compress: config.compress,
+                    append: true,
+                })?)
+            }
+            Destination::Syslog => Self::Syslog(connect(SYSLOG_SOCKET)?),
+            Destination::Journald => Self::Journald(connect(JOURNALD_SOCKET)?),
+        })
+    }
+
+    /// Write a formatted line
+    pub fn write_line(&mut self, level: Level, line: &str) -> Result<()> {
+        match self {
+            Self::Stderr => writeln!(io::stderr(), "{}", line)?,
+            Self::File(file) => file.write(format!("{}\n", line).as_bytes())?,
+            Self::Syslog(socket) => {
+                let message = format!(
+                    "<{}>{}[{}]: {}",
+                    LOG_DAEMON | severity(level),
+                    IDENTIFIER,
+                    std::process::id(),
+                    line
+                );
+                socket.send(message.as_bytes())?;
+            }
+            Self::Journald(_) => unreachable!("journald takes fields"),
+        }
+        Ok(())
+    }
+
+    /// Send a record to the journal as fields, the keys are uppercased
+    pub fn write_fields(
+        &mut self,
+        level: Level,
+        message: &str,
+        fields: &[(String, String)],
+    ) -> Result<()> {
+        let socket = match self {
+            Self::Journald(socket) => socket,
+            _ => unreachable!("only journald takes fields"),
+        };
+        let mut datagram = Vec::new();
+        journal_field(&mut datagram, "MESSAGE", message);
+        journal_field(&mut datagram, "PRIORITY", &severity(level).to_string());
+        journal_field(&mut datagram, "SYSLOG_IDENTIFIER", IDENTIFIER);
+        for (key, value) in fields {
+            journal_field(&mut datagram, &journal_key(key), value);
+        }
+        socket.send(&datagram)?;
+        Ok(())
+    }
+}
+
+fn connect(path: &str) -> Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound().context("Failed to create a datagram socket")?;
+    socket
+        .connect(path)
+        .with_context(|| format!("Failed to connect to {}", path))?;
+    Ok(socket)
+}
+
+/// Syslog severity of a level
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Journal field names are uppercase letters, digits and underscores
+fn journal_key(key: &str) -> String {
+    key.chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect()
+}
+
+/// Append a field in the journal native format, which needs a length prefix for multiline
+/// values
+fn journal_field(datagram: &mut Vec<u8>, key: &str, value: &str) {
+    datagram.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_field() {
+        let mut datagram = Vec::new();
+        journal_field(&mut datagram, &journal_key("process"), "web-0");
+        journal_field(&mut datagram, "MESSAGE", "a\nb");
+        let mut expected = b"PROCESS=web-0\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(datagram, expected);
+    }
+}
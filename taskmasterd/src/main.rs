@@ -1,27 +1,38 @@
+mod configdiff;
 mod daemon;
+mod event;
 mod job;
 mod jobs;
-mod listener;
-mod sleeper;
+mod logger;
+mod procfs;
+mod selector;
 mod socket;
+mod statefile;
+mod tail;
+mod timers;
+mod upgrade;
+mod watch;
 
+use crate::event::{Event, EventLoop};
+use crate::job::process::output::Stream;
 use crate::jobs::load_config_file;
-use crate::sleeper::Sleeper;
 use crate::socket::Socket;
+use crate::statefile::{SavedState, StateFile, STATE_FILE};
+use crate::tail::Tailers;
+use crate::watch::Watchers;
 use anyhow::{Context, Result};
 use clap::Parser;
 use dirs::home_dir;
+use job::process::Process;
 use jobs::Jobs;
-use listener::Action;
-use signal_hook::consts::signal::{SIGHUP, SIGINT, SIGQUIT, SIGTERM};
+use log::{error, info, warn};
+use nix::sys::signal::Signal;
 use std::fs;
-use std::path::PathBuf;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::str::FromStr;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use taskmasterproto::{ErrorCode, Request, Response, TargetError, SOCKET_PATH};
 
 pub const FILES_DIR: &str = ".taskmasterd";
-const SOCKET_PATH: &str = ".taskmasterd/taskmasterd.sock";
 
 #[derive(Parser)]
 #[command(version, author, about)]
@@ -30,89 +41,429 @@ struct Opts {
     nodaemon: bool,
 }
 
-fn create_signal_handler() -> Result<(Arc<AtomicBool>, Arc<AtomicBool>)> {
-    let hup = Arc::new(AtomicBool::new(false));
-    let term = Arc::new(AtomicBool::new(false));
-    signal_hook::flag::register(SIGHUP, Arc::clone(&hup))
-        .context("Failed to register SIGHUP handler")?;
-    signal_hook::flag::register(SIGTERM, Arc::clone(&term))
-        .context("Failed to register SIGTERM handler")?;
-    signal_hook::flag::register(SIGINT, Arc::clone(&term))
-        .context("Failed to register SIGTERM handler")?;
-    signal_hook::flag::register(SIGQUIT, Arc::clone(&term))
-        .context("Failed to register SIGTERM handler")?;
-    Ok((hup, term))
-}
-
 pub fn main_loop() -> Result<()> {
-    let socket_path = home_dir()
-        .context("could not find home directory")?
-        .join(SOCKET_PATH);
-    let socket = Socket::new(&socket_path)?;
-    let (hup, term) = create_signal_handler()?;
-    let mut jobs = Jobs::new().context("Jobs creation failed")?;
-    let mut response = String::new();
-    let mut sleeper = Sleeper::new(100)?;
-    jobs.auto_start();
-    while !term.load(Ordering::Relaxed) {
-        if hup.load(Ordering::Relaxed) {
-            hup.store(false, Ordering::Relaxed);
-            jobs.reread().context("Jobs reload failed")?;
+    let home = home_dir().context("could not find home directory")?;
+    // resolved now, the binary may be replaced by the time of an upgrade
+    let exe = std::env::current_exe().context("could not find the daemon executable")?;
+    let handoff = upgrade::take().context("Failed to take over from the previous daemon")?;
+    let mut socket = match &handoff {
+        Some(handoff) => Socket::from_listener(handoff.listener),
+        None => Socket::new(&home.join(SOCKET_PATH))?,
+    };
+    // orphaned descendants of the processes are reparented to the daemon instead of init
+    // SAFETY: prctl only sets a flag on the daemon
+    if unsafe { nix::libc::prctl(nix::libc::PR_SET_CHILD_SUBREAPER, 1) } == -1 {
+        return Err(nix::errno::Errno::last()).context("Failed to become a child subreaper");
+    }
+    let mut events = EventLoop::new(&socket)?;
+    let mut state_file = StateFile::open(home.join(STATE_FILE));
+    let mut jobs = match handoff {
+        Some(handoff) => {
+            let mut jobs = Jobs::take_over(handoff).context("Jobs take over failed")?;
+            info!("Took over from the previous daemon");
+            // children may have exited during the exec
+            jobs.reap_children();
+            jobs
         }
-        if let Some(stream) = socket.read(&mut response)? {
-            let action = Action::from_str(&response)?;
-            match action {
-                Action::Start(name) => jobs.start(&name),
-                Action::Stop(name) => jobs.stop(&name).context("Job stop failed")?,
-                Action::Restart(name) => jobs.restart(&name).context("Job restart failed")?,
-                Action::Status(name) => {
-                    let status = jobs.status(&name).context("Job status failed")?;
-                    socket.write(&status, stream)?;
+        None => {
+            let mut jobs = Jobs::new().context("Jobs creation failed")?;
+            jobs.restore(state_file.saved());
+            jobs
+        }
+    };
+    let mut watchers = Watchers::default();
+    let mut tailers = Tailers::default();
+    let mut shutting_down = false;
+    let mut upgrading = false;
+    watch_all(jobs.register_outputs(), |fd| events.watch_output(fd));
+    watch_all(jobs.register_pidfds(), |fd| events.watch_exit(fd));
+    watch_all(jobs.register_notify_sockets(), |fd| events.watch_notify(fd));
+    while !shutting_down || jobs.is_running() {
+        for event in events.wait(jobs.next_deadline()) {
+            let shutdown = match event {
+                Event::Signal(Signal::SIGCHLD) => {
+                    jobs.reap_children();
+                    false
+                }
+                Event::Signal(Signal::SIGHUP) => {
+                    if let Err(e) = jobs
+                        .read_config()
+                        .and_then(|new_jobs| jobs.update(new_jobs))
+                    {
+                        error!("Jobs update failed: {:#}", e);
+                    }
+                    false
+                }
+                Event::Signal(_) => true,
+                Event::Connection => {
+                    for fd in socket.accept() {
+                        tailers.forget(fd);
+                        watchers.forget(fd);
+                        if let Err(e) = events.watch(fd) {
+                            error!("Dropping client: {:#}", e);
+                            socket.disconnect(fd);
+                        }
+                    }
+                    false
+                }
+                Event::Client(fd) => {
+                    let mut shutdown = false;
+                    for request in socket.receive(fd).unwrap_or_default() {
+                        shutdown |= request == Request::Shutdown;
+                        match request {
+                            Request::Watch { targets } => {
+                                if let Err(response) = watch(&mut watchers, &jobs, fd, &targets) {
+                                    socket.send(fd, &response);
+                                }
+                            }
+                            Request::Tail {
+                                target,
+                                stderr,
+                                follow,
+                                lines,
+                            } => {
+                                let stream = if stderr {
+                                    Stream::Stderr
+                                } else {
+                                    Stream::Stdout
+                                };
+                                let response =
+                                    tail(&mut tailers, &jobs, fd, &target, stream, follow, lines);
+                                socket.send(fd, &response);
+                            }
+                            Request::Attach { target } => {
+                                let response = attach(&mut tailers, &jobs, fd, &target);
+                                socket.send(fd, &response);
+                            }
+                            Request::Input { data } => {
+                                let result = match tailers.attached(fd) {
+                                    Some(process) => {
+                                        jobs.write_input(process, &data).map_err(|e| {
+                                            Response::error(ErrorCode::Internal, format!("{:#}", e))
+                                        })
+                                    }
+                                    None => Err(Response::error(
+                                        ErrorCode::InvalidRequest,
+                                        "Not attached to a process",
+                                    )),
+                                };
+                                if let Err(response) = result {
+                                    socket.send(fd, &response);
+                                }
+                            }
+                            Request::Upgrade => {
+                                let response = if shutting_down {
+                                    Response::error(
+                                        ErrorCode::InvalidRequest,
+                                        "The daemon is shutting down",
+                                    )
+                                } else if jobs.is_updating() {
+                                    // the new image would not know about the pending changes
+                                    Response::error(
+                                        ErrorCode::InvalidRequest,
+                                        "An update is in progress, retry once it is done",
+                                    )
+                                } else if jobs.is_rolling() {
+                                    // nor about the batches left to restart
+                                    Response::error(
+                                        ErrorCode::InvalidRequest,
+                                        "A rolling restart is in progress, retry once it is done",
+                                    )
+                                } else if jobs.is_queued() {
+                                    // nor about the processes waiting for other ones
+                                    Response::error(
+                                        ErrorCode::InvalidRequest,
+                                        "Processes wait for their dependencies, retry once they started or stopped",
+                                    )
+                                } else {
+                                    upgrading = true;
+                                    Response::Ok
+                                };
+                                socket.send(fd, &response);
+                            }
+                            request => {
+                                let response = handle_request(&mut jobs, request);
+                                socket.send(fd, &response);
+                            }
+                        }
+                    }
+                    shutdown
+                }
+                Event::Writable(fd) => {
+                    socket.flush(fd);
+                    false
+                }
+                Event::Exit(fd) => {
+                    jobs.reap_adopted(fd);
+                    false
                 }
-                Action::Reload => {
-                    jobs.reload().context("Jobs reload failed")?;
+                Event::Notify(fd) => {
+                    jobs.read_notifications(fd);
+                    false
                 }
-                Action::Shutdown => {
-                    break;
+                Event::Probe(fd) => {
+                    jobs.poll_probe(fd);
+                    false
                 }
-                Action::Load(path) => {
-                    if let Ok(new_jobs) = load_config_file(PathBuf::from(path.clone())) {
-                        jobs.load_new_jobs(new_jobs).context("Jobs load failed")?;
-                    } else {
-                        // if the config file is invalid, we keep the old one
-                        socket.write("Invalid config file", stream)?;
-                        eprintln!("Received invalid config file: {}", path);
+                Event::Output(fd) => {
+                    if let Some((process, stream, data)) = jobs.read_output(fd) {
+                        tailers.send(&mut socket, process, stream, &data);
                     }
+                    false
                 }
+            };
+            if shutdown && !shutting_down {
+                info!("Shutting down");
+                shutting_down = true;
+                jobs.stop_all();
             }
-            response.clear();
         }
-        jobs.check_status().context("Jobs status check failed")?;
-        sleeper.sleep()?;
+        jobs.check_timers();
+        jobs.advance_dependencies();
+        if !shutting_down {
+            jobs.apply_pending();
+            jobs.advance_rollouts();
+        }
+        watch_all(jobs.register_outputs(), |fd| events.watch_output(fd));
+        watch_all(jobs.register_notify_sockets(), |fd| events.watch_notify(fd));
+        watch_all(jobs.register_probes(), |fd| events.watch_probe(fd));
+        watchers.notify(&jobs, &mut socket);
+        for (fd, writable) in socket.writable_changes() {
+            if let Err(e) = events.watch_writable(fd, writable) {
+                error!("Dropping client: {:#}", e);
+                socket.disconnect(fd);
+            }
+        }
+        if let Err(e) = state_file.save(jobs.saved_state()) {
+            error!("Failed to save the state: {:#}", e);
+        }
+        if upgrading && !shutting_down {
+            upgrading = false;
+            info!("Upgrading to {}", exe.display());
+            let Err(e) = upgrade::exec(&exe, &jobs.hand_off(socket.as_raw_fd()));
+            error!("Upgrade failed: {:#}", e);
+        }
+    }
+    // nothing is left to adopt, and the next daemon starts from the config
+    if let Err(e) = state_file.save(SavedState::default()) {
+        error!("Failed to save the state: {:#}", e);
     }
-    println!("Shutting down");
-    jobs.stop_all().context("Jobs stop failed")?;
-    try_wait_processes_end(&mut jobs, &mut sleeper)?;
-    println!("All jobs stopped");
+    info!("All jobs stopped");
     Ok(())
 }
 
-fn try_wait_processes_end(jobs: &mut Jobs, sleeper: &mut Sleeper) -> Result<()> {
-    while jobs.programs.iter().any(|p| p.1.is_running()) {
-        jobs.check_status().context("Jobs status check failed")?;
-        sleeper.sleep()?;
+/// Watch each of `fds` with `watch`, logging the ones that cannot be watched
+fn watch_all(fds: Vec<RawFd>, watch: impl Fn(RawFd) -> Result<()>) {
+    for fd in fds {
+        if let Err(e) = watch(fd) {
+            error!(fd = fd; "{:#}", e);
+        }
+    }
+}
+
+/// Carry out a request from the control socket
+///
+/// Failures are reported to the client, they never stop the daemon.
+fn handle_request(jobs: &mut Jobs, request: Request) -> Response {
+    // nothing tells when a descendant deeper than a child gets orphaned
+    jobs.adopt_orphans();
+    match request {
+        Request::Hello { .. } => unreachable!("handshakes are handled by the socket"),
+        Request::Watch { .. }
+        | Request::Tail { .. }
+        | Request::Attach { .. }
+        | Request::Input { .. } => unreachable!("streams are handled by the main loop"),
+        Request::Upgrade => unreachable!("upgrades are handled by the main loop"),
+        Request::Start { targets } => Response::Results {
+            results: jobs.start(&targets),
+        },
+        Request::Stop { targets } => Response::Results {
+            results: jobs.stop(&targets),
+        },
+        Request::Restart {
+            targets,
+            rolling,
+            batch,
+            pause,
+        } => Response::Results {
+            results: jobs.apply(&targets, |job, i| {
+                match job.rolling(rolling, batch, pause) {
+                    Some(rolling) => job.queue_restart(i, rolling),
+                    None => job.restart_process(i),
+                }
+            }),
+        },
+        Request::Signal { signal, targets } => match parse_signal(&signal) {
+            Ok(signal) => Response::Results {
+                results: jobs.apply(&targets, |job, i| job.signal_process(i, signal)),
+            },
+            Err(e) => Response::error(ErrorCode::InvalidRequest, e),
+        },
+        Request::Rotate { targets, reopen } => Response::Results {
+            results: jobs.apply(&targets, |job, i| job.rotate_logs(i, reopen)),
+        },
+        Request::Scale { program, numprocs } => Response::Results {
+            results: vec![jobs.scale(&program, numprocs)],
+        },
+        Request::Status { targets } => {
+            let (programs, not_found) = jobs.status(&targets);
+            Response::Status {
+                programs,
+                not_found,
+            }
+        }
+        Request::Reread => match jobs.read_config() {
+            Ok(new_jobs) => Response::Diff(Box::new(jobs.diff(&new_jobs))),
+            Err(e) => Response::error(ErrorCode::InvalidConfig, format!("{:#}", e)),
+        },
+        Request::Diff { path } => match load_config_file(path) {
+            Ok(new_jobs) => Response::Diff(Box::new(jobs.diff(&new_jobs))),
+            Err(e) => Response::error(ErrorCode::InvalidConfig, format!("{:#}", e)),
+        },
+        Request::Update => update(jobs, jobs.read_config()),
+        Request::Shutdown => Response::Ok,
+        Request::Load { path } => {
+            let new_jobs = load_config_file(path.clone());
+            if new_jobs.is_err() {
+                warn!("Received invalid config file: {}", path.display());
+            }
+            update(jobs, new_jobs)
+        }
+    }
+}
+
+/// Apply a config, unless it could not be loaded in which case the running one is kept
+fn update(jobs: &mut Jobs, new_jobs: Result<Jobs>) -> Response {
+    match new_jobs {
+        Ok(new_jobs) => match jobs.update(new_jobs) {
+            Ok(diff) => Response::Diff(Box::new(diff)),
+            Err(e) => Response::error(ErrorCode::Internal, format!("{:#}", e)),
+        },
+        Err(e) => Response::error(ErrorCode::InvalidConfig, format!("{:#}", e)),
     }
+}
+
+/// Make a client follow the processes selected by `targets`
+fn watch(
+    watchers: &mut Watchers,
+    jobs: &Jobs,
+    fd: RawFd,
+    targets: &[String],
+) -> Result<(), Response> {
+    let mut processes = Vec::new();
+    for target in targets {
+        match jobs.select(target) {
+            Ok(selected) if !selected.is_empty() => processes.extend(selected),
+            _ => {
+                return Err(Response::error(
+                    ErrorCode::NotFound,
+                    format!("{}: {}", target, TargetError::NotFound),
+                ))
+            }
+        }
+    }
+    watchers.add(fd, jobs, processes);
     Ok(())
 }
 
+/// Resolve a target that must select a single process
+fn select_one<'a>(jobs: &'a Jobs, target: &str, command: &str) -> Result<&'a Process, Response> {
+    let (name, index) = match jobs.select(target).as_deref() {
+        Ok([process]) => *process,
+        Ok([]) => {
+            return Err(Response::error(
+                ErrorCode::NotFound,
+                format!("{}: {}", target, TargetError::NotFound),
+            ))
+        }
+        Ok(processes) => {
+            return Err(Response::error(
+                ErrorCode::InvalidRequest,
+                format!(
+                    "{} selects {} processes, {} needs a single one",
+                    target,
+                    processes.len(),
+                    command
+                ),
+            ))
+        }
+        Err(e) => {
+            return Err(Response::error(
+                ErrorCode::InvalidRequest,
+                format!("{:#}", e),
+            ))
+        }
+    };
+    Ok(&jobs.programs[name].processes[index])
+}
+
+/// Answer a tail request, and follow the output of the process if asked to
+fn tail(
+    tailers: &mut Tailers,
+    jobs: &Jobs,
+    fd: RawFd,
+    target: &str,
+    stream: Stream,
+    follow: bool,
+    lines: usize,
+) -> Response {
+    let process = match select_one(jobs, target, "tail") {
+        Ok(process) => process,
+        Err(response) => return response,
+    };
+    if follow {
+        tailers.add(fd, &process.name, stream);
+    }
+    Response::Output {
+        process: process.name.clone(),
+        stderr: stream == Stream::Stderr,
+        data: process.tail(stream, lines),
+    }
+}
+
+/// Attach the client to the output and input of a process
+fn attach(tailers: &mut Tailers, jobs: &Jobs, fd: RawFd, target: &str) -> Response {
+    let process = match select_one(jobs, target, "attach") {
+        Ok(process) => process,
+        Err(response) => return response,
+    };
+    if !process.has_stdin() {
+        return Response::error(
+            ErrorCode::InvalidRequest,
+            format!(
+                "{} does not keep its stdin open, set stdin or tty in its config",
+                process.name
+            ),
+        );
+    }
+    tailers.attach(fd, &process.name);
+    Response::Attached {
+        process: process.name.clone(),
+        tty: process.has_tty(),
+    }
+}
+
+/// Parse a signal name, with or without its `SIG` prefix
+fn parse_signal(name: &str) -> Result<Signal> {
+    let name = name.to_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    Signal::from_str(&name).with_context(|| format!("Invalid signal {}", name))
+}
+
 fn main() -> Result<()> {
     let opts = Opts::parse();
+    logger::init()?;
     // create a directory for the tmp files if it doesn't exist
     let path = home_dir()
         .context("could not find home directory")?
         .join(FILES_DIR);
     fs::create_dir_all(path).context("could not create files directory")?;
-    if opts.nodaemon {
+    // the image started by an upgrade is already daemonized
+    if opts.nodaemon || upgrade::is_handed_off() {
         main_loop()?;
     } else {
         daemon::init()?;
@@ -0,0 +1,113 @@
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::unistd::Pid;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+
+/// Ancestry and start time of a process, as read from `/proc/<pid>/stat`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcStat {
+    pub pid: Pid,
+    pub ppid: Pid,
+    pub pgid: Pid,
+    pub sid: Pid,
+    /// Time at which the process started, in clock ticks since boot
+    pub starttime: u64,
+}
+
+impl ProcStat {
+    /// Parse the content of a stat file, `None` for zombies and malformed files
+    fn parse(stat: &str) -> Option<Self> {
+        // the command name is parenthesized and may contain spaces or parentheses
+        let (pid, rest) = stat.split_once(" (")?;
+        let (_, rest) = rest.rsplit_once(") ")?;
+        let mut fields = rest.split(' ');
+        if fields.next()? == "Z" {
+            return None;
+        }
+        let mut next_pid = || fields.next()?.parse().ok().map(Pid::from_raw);
+        let (ppid, pgid, sid) = (next_pid()?, next_pid()?, next_pid()?);
+        // the start time is the 22nd field, the session id was the 6th
+        Some(Self {
+            pid: Pid::from_raw(pid.parse().ok()?),
+            ppid,
+            pgid,
+            sid,
+            starttime: fields.nth(15)?.parse().ok()?,
+        })
+    }
+}
+
+/// Read the stat of a process, `None` if it is gone or a zombie
+pub fn stat(pid: Pid) -> Option<ProcStat> {
+    ProcStat::parse(&fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?)
+}
+
+/// Open a pidfd referring to `pid`, which becomes readable once the process exits
+pub fn pidfd_open(pid: Pid) -> nix::Result<OwnedFd> {
+    // SAFETY: the syscall only takes integers
+    let fd = unsafe { nix::libc::syscall(nix::libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    // SAFETY: on success the syscall returns a new fd that nothing else owns
+    Errno::result(fd).map(|fd| unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Children of `pid`
+pub fn children(pid: Pid) -> Result<Vec<Pid>> {
+    match fs::read_to_string(format!("/proc/{0}/task/{0}/children", pid)) {
+        Ok(children) => Ok(children
+            .split_whitespace()
+            .filter_map(|child| child.parse().ok())
+            .map(Pid::from_raw)
+            .collect()),
+        // the kernel was built without CONFIG_PROC_CHILDREN
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(processes()?
+            .into_iter()
+            .filter(|p| p.ppid == pid)
+            .map(|p| p.pid)
+            .collect()),
+        Err(e) => Err(e).with_context(|| format!("Failed to list the children of {}", pid)),
+    }
+}
+
+/// Every live process of the system
+fn processes() -> Result<Vec<ProcStat>> {
+    let mut processes = Vec::new();
+    for entry in fs::read_dir("/proc").context("Failed to list /proc")? {
+        let entry = entry.context("Failed to list /proc")?;
+        let is_process = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()));
+        if !is_process {
+            continue;
+        }
+        // the process may exit while we are reading
+        if let Ok(stat) = fs::read_to_string(entry.path().join("stat")) {
+            processes.extend(ProcStat::parse(&stat));
+        }
+    }
+    Ok(processes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let parsed = ProcStat::parse(
+            "42 (a) b (c)) S 1 40 30 0 -1 4194560 95 0 0 0 1 2 0 0 20 0 1 0 1234 5 6",
+        )
+        .unwrap();
+        assert_eq!(parsed.pid, Pid::from_raw(42));
+        assert_eq!(parsed.ppid, Pid::from_raw(1));
+        assert_eq!(parsed.pgid, Pid::from_raw(40));
+        assert_eq!(parsed.sid, Pid::from_raw(30));
+        assert_eq!(parsed.starttime, 1234);
+        assert!(ProcStat::parse("42 (zombie) Z 1 40 30 0").is_none());
+        let me = nix::unistd::getpid();
+        assert!(processes().unwrap().iter().any(|p| p.pid == me));
+        assert_eq!(stat(me).unwrap().pid, me);
+    }
+}
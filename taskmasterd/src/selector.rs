@@ -0,0 +1,101 @@
+use anyhow::{Context, Result};
+use glob::Pattern;
+use std::str::FromStr;
+
+/// Target of a control command
+#[derive(Debug, Clone)]
+pub enum Selector {
+    /// Every process
+    All,
+    /// A program or a single process, by name
+    Name(String),
+    /// Every process of a program or of the programs of a group, written `name:*`
+    Program(String),
+    /// Every process of the programs of a group, written `group:`
+    Group(String),
+    /// A program or a single process of a group, written `group:name`
+    Member(String, String),
+    /// Programs or processes whose name matches a glob
+    Glob(Pattern),
+}
+
+impl FromStr for Selector {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            Ok(Selector::All)
+        } else if let Some(program) = s.strip_suffix(":*") {
+            Ok(Selector::Program(program.to_string()))
+        } else if let Some(group) = s.strip_suffix(':') {
+            Ok(Selector::Group(group.to_string()))
+        } else if let Some((group, name)) = s.split_once(':') {
+            Ok(Selector::Member(group.to_string(), name.to_string()))
+        } else if s.contains(['*', '?', '[']) {
+            Ok(Selector::Glob(
+                Pattern::new(s).with_context(|| format!("Invalid pattern {}", s))?,
+            ))
+        } else {
+            Ok(Selector::Name(s.to_string()))
+        }
+    }
+}
+
+impl Selector {
+    /// Whether the selector picks every process of the program `name`, member of `group`
+    pub fn matches_program(&self, name: &str, group: Option<&str>) -> bool {
+        match self {
+            Selector::All => true,
+            Selector::Name(n) => n == name,
+            Selector::Program(n) => n == name || group == Some(n),
+            Selector::Group(g) => group == Some(g),
+            Selector::Member(g, n) => group == Some(g) && n == name,
+            Selector::Glob(pattern) => pattern.matches(name),
+        }
+    }
+
+    /// Whether the selector picks the process `name`, of a program member of `group`
+    pub fn matches_process(&self, name: &str, group: Option<&str>) -> bool {
+        match self {
+            Selector::All => true,
+            Selector::Name(n) => n == name,
+            Selector::Program(_) | Selector::Group(_) => false,
+            Selector::Member(g, n) => group == Some(g) && n == name,
+            Selector::Glob(pattern) => pattern.matches(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selector() {
+        let glob = Selector::from_str("web*").unwrap();
+        assert!(glob.matches_program("web", None) && glob.matches_process("webapp-2", None));
+        assert!(!glob.matches_program("api", None));
+        let program = Selector::from_str("web:*").unwrap();
+        assert!(program.matches_program("web", None) && !program.matches_process("web-0", None));
+        let name = Selector::from_str("web-1").unwrap();
+        assert!(!name.matches_program("web", None) && name.matches_process("web-1", None));
+        assert!(Selector::from_str("all")
+            .unwrap()
+            .matches_program("anything", None));
+        let group = Selector::from_str("app:").unwrap();
+        assert!(group.matches_program("web", Some("app")));
+        assert!(!group.matches_program("app", None));
+        assert!(Selector::from_str("app:*")
+            .unwrap()
+            .matches_program("worker", Some("app")));
+        let member = Selector::from_str("app:web").unwrap();
+        assert!(member.matches_program("web", Some("app")));
+        assert!(!member.matches_program("web", None) && !member.matches_program("web", Some("db")));
+        let process = Selector::from_str("app:web-1").unwrap();
+        assert!(
+            process.matches_process("web-1", Some("app"))
+                && !process.matches_process("web-1", None)
+        );
+        assert!(Selector::from_str("web[").is_err());
+    }
+}
@@ -1,17 +1,37 @@
 use anyhow::{Context, Result};
+use log::warn;
+use std::collections::HashMap;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::PathBuf;
+use taskmasterproto::{take_frame, write_frame, ErrorCode, Request, Response, PROTOCOL_VERSION};
+
+/// Size of the responses waiting for a client to read them past which it is dropped, so
+/// that a client that stopped reading does not make the daemon buffer without bounds
+const MAX_PENDING: usize = 1024 * 1024;
+
+/// A connected client and the bytes it sent that do not form a full frame yet
+struct Client {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+    greeted: bool,
+    /// Frames not written yet, as the client does not read them fast enough
+    pending: Vec<u8>,
+    /// Whether the main loop waits for the client to be writable
+    writable: bool,
+}
 
 pub struct Socket {
     listener: UnixListener,
+    clients: HashMap<RawFd, Client>,
 }
 
 impl Socket {
     pub fn new(path: &PathBuf) -> Result<Self> {
         if fs::metadata(path).is_ok() {
-            eprintln!("A socket is already present. Deleting...");
+            warn!("A socket is already present. Deleting...");
             fs::remove_file(path)
                 .with_context(|| format!("could not delete previous socket at {:?}", path))?;
         }
@@ -20,24 +40,232 @@ impl Socket {
         listener
             .set_nonblocking(true)
             .with_context(|| format!("could not set socket to non-blocking at {:?}", path))?;
-        Ok(Self { listener })
+        Ok(Self {
+            listener,
+            clients: HashMap::new(),
+        })
+    }
+
+    /// Take back the listening socket handed over by the previous image of the daemon
+    pub fn from_listener(fd: RawFd) -> Self {
+        Self {
+            // SAFETY: the fd was left open by the previous image, which does not exist anymore
+            listener: unsafe { UnixListener::from_raw_fd(fd) },
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Accept the pending connections and return their fds
+    pub fn accept(&mut self) -> Vec<RawFd> {
+        let mut accepted = Vec::new();
+        while let Ok((stream, _)) = self.listener.accept() {
+            if let Err(e) = stream.set_nonblocking(true) {
+                warn!("Failed to set client socket to non-blocking: {}", e);
+                continue;
+            }
+            let fd = stream.as_raw_fd();
+            self.clients.insert(
+                fd,
+                Client {
+                    stream,
+                    buffer: Vec::new(),
+                    greeted: false,
+                    pending: Vec::new(),
+                    writable: false,
+                },
+            );
+            accepted.push(fd);
+        }
+        accepted
+    }
+
+    /// Read the complete requests sent by a client
+    ///
+    /// The handshake is handled here, so only the requests that follow it are returned.
+    /// Returns `None` once the client is disconnected.
+    pub fn receive(&mut self, fd: RawFd) -> Option<Vec<Request>> {
+        let client = self.clients.get_mut(&fd)?;
+        let mut chunk = [0; 4096];
+        let mut closed = false;
+        let mut hung_up = false;
+        loop {
+            match client.stream.read(&mut chunk) {
+                Ok(0) => {
+                    hung_up = true;
+                    break;
+                }
+                Ok(n) => client.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("Failed to read from client: {}", e);
+                    closed = true;
+                    break;
+                }
+            }
+        }
+        let mut requests = Vec::new();
+        let mut replies = Vec::new();
+        loop {
+            let payload = match take_frame(&mut client.buffer) {
+                Ok(Some(payload)) => payload,
+                Ok(None) => break,
+                Err(e) => {
+                    // the framing is lost, there is no way to recover
+                    warn!("Dropping client: {}", e);
+                    closed = true;
+                    break;
+                }
+            };
+            match serde_json::from_slice::<Request>(&payload) {
+                Ok(Request::Hello { version }) if client.greeted => {
+                    replies.push(Response::error(
+                        ErrorCode::InvalidRequest,
+                        format!("Unexpected handshake (version {})", version),
+                    ));
+                }
+                Ok(Request::Hello { version }) if version == PROTOCOL_VERSION => {
+                    client.greeted = true;
+                    replies.push(Response::Hello {
+                        version: PROTOCOL_VERSION,
+                    });
+                }
+                Ok(Request::Hello { version }) => {
+                    replies.push(Response::error(
+                        ErrorCode::VersionMismatch,
+                        format!(
+                            "Client speaks protocol version {}, daemon speaks version {}",
+                            version, PROTOCOL_VERSION
+                        ),
+                    ));
+                    closed = true;
+                    break;
+                }
+                Ok(_) if !client.greeted => {
+                    replies.push(Response::error(
+                        ErrorCode::HandshakeRequired,
+                        "The first request must be a handshake",
+                    ));
+                    closed = true;
+                    break;
+                }
+                Ok(request) => requests.push(request),
+                Err(e) => replies.push(Response::error(ErrorCode::InvalidRequest, e)),
+            }
+        }
+        for reply in replies {
+            self.send(fd, &reply);
+        }
+        // a client that hung up is kept until its last requests are answered,
+        // epoll reports the hang up again on the next wait
+        if closed || (hung_up && requests.is_empty()) {
+            self.disconnect(fd);
+            return None;
+        }
+        Some(requests)
+    }
+
+    /// Send a response to a client, without blocking
+    ///
+    /// What the client does not take right away is queued, and written by `flush` once the
+    /// client is writable. The client is disconnected if that fails, or if too much is
+    /// queued already.
+    pub fn send(&mut self, fd: RawFd, response: &Response) {
+        let Some(client) = self.clients.get_mut(&fd) else {
+            return;
+        };
+        if client.pending.len() > MAX_PENDING {
+            warn!(
+                "Dropping client: {} bytes of responses are waiting for it to read them",
+                client.pending.len()
+            );
+            self.disconnect(fd);
+            return;
+        }
+        if let Err(e) = write_frame(&mut client.pending, response) {
+            warn!("Failed to send response to client: {:#}", e);
+            self.disconnect(fd);
+            return;
+        }
+        self.flush(fd);
+    }
+
+    /// Write as much of the queued responses as the client takes without blocking,
+    /// disconnecting it if that fails
+    pub fn flush(&mut self, fd: RawFd) {
+        let Some(client) = self.clients.get_mut(&fd) else {
+            return;
+        };
+        let mut written = 0;
+        let result = loop {
+            if written == client.pending.len() {
+                break Ok(());
+            }
+            match client.stream.write(&client.pending[written..]) {
+                Ok(0) => break Err(io::Error::from(ErrorKind::WriteZero)),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e),
+            }
+        };
+        client.pending.drain(..written);
+        if let Err(e) = result {
+            warn!("Failed to send response to client: {}", e);
+            self.disconnect(fd);
+        }
     }
 
-    pub fn read(&self, response: &mut String) -> Result<Option<UnixStream>> {
-        if let Ok((mut stream, _)) = self.listener.accept() {
-            stream
-                .read_to_string(response)
-                .context("Failed at reading the request")?;
-            Ok(Some(stream))
-        } else {
-            Ok(None)
+    /// Clients that now have to be watched for being writable, or no longer, because their
+    /// queue of responses filled up or emptied since the last call
+    pub fn writable_changes(&mut self) -> Vec<(RawFd, bool)> {
+        let mut changes = Vec::new();
+        for (fd, client) in self.clients.iter_mut() {
+            let writable = !client.pending.is_empty();
+            if writable != client.writable {
+                client.writable = writable;
+                changes.push((*fd, writable));
+            }
         }
+        changes
+    }
+
+    pub fn is_connected(&self, fd: RawFd) -> bool {
+        self.clients.contains_key(&fd)
     }
 
-    pub fn write(&self, response: &str, mut stream: UnixStream) -> Result<()> {
-        stream
-            .write_all(response.as_bytes())
-            .context("Failed at writing the response")?;
-        Ok(())
+    pub fn disconnect(&mut self, fd: RawFd) {
+        self.clients.remove(&fd);
+    }
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_not_reading() {
+        let path = std::env::temp_dir().join(format!("taskmasterd-socket-{}", std::process::id()));
+        let mut socket = Socket::new(&path).unwrap();
+        let client = UnixStream::connect(&path).unwrap();
+        let fd = socket.accept()[0];
+        let response = Response::Output {
+            process: "chatty-0".to_string(),
+            stderr: false,
+            data: vec![b'x'; 64 * 1024],
+        };
+        // the responses are queued without blocking, until the client is dropped
+        for _ in 0..(MAX_PENDING / (64 * 1024) + 64) {
+            socket.send(fd, &response);
+        }
+        assert!(!socket.is_connected(fd));
+        drop(client);
+        fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,107 @@
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// State file, relative to the home directory
+pub const STATE_FILE: &str = ".taskmasterd/state.json";
+
+/// What a later daemon needs to adopt the running processes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedState {
+    /// Running processes by name
+    pub processes: BTreeMap<String, SavedProcess>,
+    /// Number of processes of the programs, which differs from the config once scaled
+    #[serde(default)]
+    pub numprocs: BTreeMap<String, u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedProcess {
+    pub pid: i32,
+    /// Start time of the process in clock ticks since boot, as found in `/proc/<pid>/stat`,
+    /// which tells it apart from a later process reusing its pid
+    pub starttime: u64,
+    /// Time at which the process was spawned, in milliseconds since the epoch
+    pub spawned_at: u64,
+    pub status: SavedStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SavedStatus {
+    Starting { tries: u32 },
+    Running,
+    Stopping { restart: bool },
+}
+
+/// State file kept up to date with the running processes
+pub struct StateFile {
+    path: PathBuf,
+    saved: SavedState,
+}
+
+impl StateFile {
+    /// Read the state left by a previous daemon, an unreadable file is ignored
+    pub fn open(path: PathBuf) -> Self {
+        let saved = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!("Ignoring invalid state file {}: {}", path.display(), e);
+                SavedState::default()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => SavedState::default(),
+            Err(e) => {
+                warn!("Ignoring unreadable state file {}: {}", path.display(), e);
+                SavedState::default()
+            }
+        };
+        Self { path, saved }
+    }
+
+    pub fn saved(&self) -> &SavedState {
+        &self.saved
+    }
+
+    /// Write `state` if it changed, replacing the file atomically
+    pub fn save(&mut self, state: SavedState) -> Result<()> {
+        if state == self.saved {
+            return Ok(());
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec(&state)?)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        self.saved = state;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_open() {
+        let path = std::env::temp_dir().join(format!("taskmasterd-state-{}", std::process::id()));
+        let mut state = SavedState::default();
+        state.processes.insert(
+            "web-0".to_string(),
+            SavedProcess {
+                pid: 42,
+                starttime: 1234,
+                spawned_at: 5678,
+                status: SavedStatus::Starting { tries: 2 },
+            },
+        );
+        state.numprocs.insert("web".to_string(), 3);
+        StateFile::open(path.clone()).save(state.clone()).unwrap();
+        assert_eq!(StateFile::open(path.clone()).saved(), &state);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(StateFile::open(path).saved(), &SavedState::default());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::job::process::output::Stream;
+use crate::socket::Socket;
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
+use taskmasterproto::Response;
+
+/// A process stream followed by a client
+struct Tailed {
+    fd: RawFd,
+    process: String,
+    stream: Stream,
+}
+
+/// Clients following the output of processes with a `tail` or `attach` request
+#[derive(Default)]
+pub struct Tailers {
+    tails: Vec<Tailed>,
+    /// Clients attached to a process, that can write to its stdin
+    attached: HashMap<RawFd, String>,
+}
+
+impl Tailers {
+    pub fn add(&mut self, fd: RawFd, process: &str, stream: Stream) {
+        self.tails.push(Tailed {
+            fd,
+            process: process.to_string(),
+            stream,
+        });
+    }
+
+    /// Follow both streams of `process`, and let the client write to its stdin
+    pub fn attach(&mut self, fd: RawFd, process: &str) {
+        self.add(fd, process, Stream::Stdout);
+        self.add(fd, process, Stream::Stderr);
+        self.attached.insert(fd, process.to_string());
+    }
+
+    /// The process the client is attached to
+    pub fn attached(&self, fd: RawFd) -> Option<&str> {
+        self.attached.get(&fd).map(String::as_str)
+    }
+
+    /// Stop following anything for a client, whose fd may be reused by a new one
+    pub fn forget(&mut self, fd: RawFd) {
+        self.tails.retain(|tail| tail.fd != fd);
+        self.attached.remove(&fd);
+    }
+
+    /// Send what `process` wrote to `stream` to the clients following it
+    pub fn send(&mut self, socket: &mut Socket, process: &str, stream: Stream, data: &[u8]) {
+        self.tails.retain(|tail| socket.is_connected(tail.fd));
+        self.attached.retain(|fd, _| socket.is_connected(*fd));
+        if data.is_empty() {
+            return;
+        }
+        let response = Response::Output {
+            process: process.to_string(),
+            stderr: stream == Stream::Stderr,
+            data: data.to_vec(),
+        };
+        for tail in &self.tails {
+            if tail.process == process && tail.stream == stream {
+                socket.send(tail.fd, &response);
+            }
+        }
+    }
+}
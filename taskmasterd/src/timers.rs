@@ -0,0 +1,63 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::Instant;
+
+/// Deadline queue keyed by name, each key having at most one pending deadline
+#[derive(Debug, Default)]
+pub struct Timers {
+    queue: BTreeSet<(Instant, String)>,
+    deadlines: HashMap<String, Instant>,
+}
+
+impl Timers {
+    /// Replace the deadline of `key`, or cancel it if `deadline` is `None`
+    pub fn schedule(&mut self, key: &str, deadline: Option<Instant>) {
+        if self.deadlines.get(key).copied() == deadline {
+            return;
+        }
+        if let Some(old) = self.deadlines.remove(key) {
+            self.queue.remove(&(old, key.to_string()));
+        }
+        if let Some(deadline) = deadline {
+            self.queue.insert((deadline, key.to_string()));
+            self.deadlines.insert(key.to_string(), deadline);
+        }
+    }
+
+    /// Earliest pending deadline
+    pub fn next(&self) -> Option<Instant> {
+        self.queue.first().map(|(deadline, _)| *deadline)
+    }
+
+    /// Remove and return the keys whose deadline is reached
+    pub fn expired(&mut self, now: Instant) -> Vec<String> {
+        let mut keys = Vec::new();
+        while let Some((deadline, _)) = self.queue.first() {
+            if *deadline > now {
+                break;
+            }
+            let (_, key) = self.queue.pop_first().expect("queue should not be empty");
+            self.deadlines.remove(&key);
+            keys.push(key);
+        }
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timers_reschedule() {
+        let now = Instant::now();
+        let mut timers = Timers::default();
+        timers.schedule("a", Some(now + Duration::from_secs(2)));
+        timers.schedule("b", Some(now + Duration::from_secs(3)));
+        timers.schedule("a", Some(now + Duration::from_secs(4)));
+        assert_eq!(timers.next(), Some(now + Duration::from_secs(3)));
+        assert_eq!(timers.expired(now + Duration::from_secs(3)), ["b"]);
+        timers.schedule("a", None);
+        assert_eq!(timers.next(), None);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::job::process::output::Stream;
+use crate::statefile::SavedProcess;
+use anyhow::{Context, Result};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::unistd::execv;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+/// Environment variable holding the fd of the handoff, set for the new image only
+const HANDOFF_ENV: &str = "TASKMASTERD_HANDOFF";
+
+/// Everything the new image of the daemon needs to resume supervision
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handoff {
+    /// Listening socket of the control socket
+    pub listener: RawFd,
+    /// Config file content the jobs were loaded from
+    pub config: Option<String>,
+    /// Path of the config file, read again by updates
+    pub path: Option<PathBuf>,
+    /// Number of processes of the programs, which differs from the config once scaled
+    #[serde(default)]
+    pub numprocs: BTreeMap<String, u32>,
+    pub processes: BTreeMap<String, ProcessHandoff>,
+}
+
+/// A process as handed over, the fds stay open across the exec
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessHandoff {
+    pub state: HandedState,
+    /// Pidfd of a process adopted from a crashed daemon, which is not a child
+    pub pidfd: Option<RawFd>,
+    /// Whether the process is not a child of the daemon
+    pub adopted: bool,
+    pub stdin: Option<RawFd>,
+    pub outputs: Vec<(RawFd, Stream)>,
+    pub sessions: Vec<i32>,
+    pub orphans: Vec<i32>,
+    /// Whether the log files were opened, they are appended to by the new image
+    pub logs: bool,
+    pub stdout_buffer: Vec<u8>,
+    pub stderr_buffer: Vec<u8>,
+    /// Notify socket of a process configured with `notify`
+    #[serde(default)]
+    pub notify: Option<RawFd>,
+    /// Status last sent by the process with `STATUS=`
+    #[serde(default)]
+    pub notice: Option<String>,
+    /// Whether the process sent `STOPPING=1`
+    #[serde(default)]
+    pub stopping: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HandedState {
+    Running(SavedProcess),
+    Backoff { tries: u32 },
+    Fatal,
+    Unexpected,
+    Exited,
+    Stopped,
+}
+
+impl Handoff {
+    fn fds(&self) -> Vec<RawFd> {
+        let mut fds = vec![self.listener];
+        for process in self.processes.values() {
+            fds.extend(process.pidfd);
+            fds.extend(process.stdin);
+            fds.extend(process.notify);
+            fds.extend(process.outputs.iter().map(|(fd, _)| *fd));
+        }
+        fds
+    }
+}
+
+/// Replace the daemon with a new image of `exe`, which takes over from `handoff`
+///
+/// Only returns if the exec failed, the daemon then goes on as before.
+pub fn exec(exe: &Path, handoff: &Handoff) -> Result<Infallible> {
+    let memfd = memfd_create(
+        &CString::new("taskmasterd-handoff")?,
+        MemFdCreateFlag::empty(),
+    )
+    .context("Failed to create the handoff memfd")?;
+    // SAFETY: memfd_create returned a new fd that nothing else owns
+    let mut memfd = unsafe { File::from_raw_fd(memfd) };
+    serde_json::to_writer(&mut memfd, handoff)?;
+    memfd.flush()?;
+    memfd.rewind()?;
+    let fds = handoff.fds();
+    for fd in fds.iter().chain([&memfd.as_raw_fd()]) {
+        set_cloexec(*fd, false)?;
+    }
+    let args = std::env::args_os()
+        .map(|arg| CString::new(arg.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+    std::env::set_var(HANDOFF_ENV, memfd.as_raw_fd().to_string());
+    let error = execv(&CString::new(exe.as_os_str().as_bytes())?, &args)
+        .with_context(|| format!("Failed to exec {}", exe.display()));
+    std::env::remove_var(HANDOFF_ENV);
+    for fd in fds {
+        set_cloexec(fd, true)?;
+    }
+    error
+}
+
+/// Whether the daemon is a new image started by `exec`
+pub fn is_handed_off() -> bool {
+    std::env::var_os(HANDOFF_ENV).is_some()
+}
+
+/// Read the handoff left by the previous image, if any
+///
+/// The fds it holds get their close-on-exec flag back.
+pub fn take() -> Result<Option<Handoff>> {
+    let fd: RawFd = match std::env::var(HANDOFF_ENV) {
+        Ok(fd) => fd.parse().context("Invalid handoff fd")?,
+        Err(_) => return Ok(None),
+    };
+    std::env::remove_var(HANDOFF_ENV);
+    // SAFETY: the previous image left the memfd open for us
+    let mut memfd = unsafe { File::from_raw_fd(fd) };
+    let mut content = Vec::new();
+    memfd.read_to_end(&mut content)?;
+    let handoff: Handoff = serde_json::from_slice(&content).context("Invalid handoff")?;
+    for fd in handoff.fds() {
+        set_cloexec(fd, true)?;
+    }
+    Ok(Some(handoff))
+}
+
+fn set_cloexec(fd: RawFd, cloexec: bool) -> Result<()> {
+    let flags = match cloexec {
+        true => FdFlag::FD_CLOEXEC,
+        false => FdFlag::empty(),
+    };
+    fcntl(fd, FcntlArg::F_SETFD(flags)).context("Failed to set close-on-exec")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statefile::SavedStatus;
+
+    #[test]
+    fn test_handoff_round_trip() {
+        let running = ProcessHandoff {
+            state: HandedState::Running(SavedProcess {
+                pid: 42,
+                starttime: 1234,
+                spawned_at: 1_700_000_000_000,
+                status: SavedStatus::Stopping { restart: true },
+            }),
+            pidfd: Some(7),
+            adopted: true,
+            stdin: Some(8),
+            outputs: vec![(9, Stream::Stdout), (10, Stream::Stderr)],
+            sessions: vec![42],
+            orphans: vec![43],
+            logs: true,
+            stdout_buffer: b"out\xff".to_vec(),
+            stderr_buffer: b"err\n".to_vec(),
+            notify: Some(11),
+            notice: Some("ready".to_string()),
+            stopping: true,
+        };
+        let backoff = ProcessHandoff {
+            state: HandedState::Backoff { tries: 2 },
+            pidfd: None,
+            adopted: false,
+            stdin: None,
+            outputs: Vec::new(),
+            sessions: Vec::new(),
+            orphans: Vec::new(),
+            logs: false,
+            stdout_buffer: Vec::new(),
+            stderr_buffer: Vec::new(),
+            notify: None,
+            notice: None,
+            stopping: false,
+        };
+        let handoff = Handoff {
+            listener: 3,
+            config: Some("programs: {}\n".to_string()),
+            path: Some(PathBuf::from("/etc/taskmasterd.yml")),
+            numprocs: BTreeMap::from([("web".to_string(), 4)]),
+            processes: BTreeMap::from([
+                ("web-0".to_string(), running),
+                ("worker-0".to_string(), backoff),
+            ]),
+        };
+        let json = serde_json::to_vec(&handoff).unwrap();
+        assert_eq!(serde_json::from_slice::<Handoff>(&json).unwrap(), handoff);
+        assert_eq!(handoff.fds(), vec![3, 7, 8, 11, 9, 10]);
+    }
+
+    /// Handoff written by an image that predates the optional fields
+    #[test]
+    fn test_older_handoff() {
+        let json = r#"{
+            "listener": 3,
+            "config": null,
+            "path": null,
+            "processes": {
+                "web-0": {
+                    "state": "stopped",
+                    "pidfd": null,
+                    "adopted": false,
+                    "stdin": null,
+                    "outputs": [[5, "stdout"]],
+                    "sessions": [],
+                    "orphans": [],
+                    "logs": false,
+                    "stdout_buffer": [],
+                    "stderr_buffer": []
+                }
+            }
+        }"#;
+        let handoff = serde_json::from_str::<Handoff>(json).unwrap();
+        assert!(handoff.numprocs.is_empty());
+        let process = &handoff.processes["web-0"];
+        assert_eq!(process.state, HandedState::Stopped);
+        assert_eq!((process.notify, process.stopping), (None, false));
+        assert_eq!(handoff.fds(), vec![3, 5]);
+    }
+}
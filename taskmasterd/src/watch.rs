@@ -0,0 +1,82 @@
+use crate::jobs::Jobs;
+use crate::socket::Socket;
+use std::collections::HashMap;
+use std::os::unix::io::RawFd;
+use taskmasterproto::{Health, ProcessState, ProcessStatus, Response};
+
+/// A process followed by a client, and the last state and health it was told about
+struct Watched {
+    program: String,
+    index: usize,
+    name: String,
+    state: Option<(ProcessState, Option<Health>)>,
+}
+
+/// Clients following the state of processes with a `watch` request
+#[derive(Default)]
+pub struct Watchers {
+    watches: HashMap<RawFd, Vec<Watched>>,
+}
+
+impl Watchers {
+    /// Follow the given processes, as program names and process indices
+    pub fn add(&mut self, fd: RawFd, jobs: &Jobs, processes: Vec<(&str, usize)>) {
+        let watched = processes.into_iter().map(|(program, index)| Watched {
+            program: program.to_string(),
+            index,
+            name: jobs.programs[program].processes[index].name.clone(),
+            state: None,
+        });
+        self.watches.entry(fd).or_default().extend(watched);
+    }
+
+    /// Stop following anything for a client, whose fd may be reused by a new one
+    pub fn forget(&mut self, fd: RawFd) {
+        self.watches.remove(&fd);
+    }
+
+    /// Send the state of the watched processes that changed since the last call
+    ///
+    /// A process removed by an update or a scale down is told about once as stopped, and no
+    /// longer followed.
+    pub fn notify(&mut self, jobs: &Jobs, socket: &mut Socket) {
+        self.watches.retain(|fd, _| socket.is_connected(*fd));
+        for (fd, watched) in self.watches.iter_mut() {
+            watched.retain_mut(|process| {
+                let status = match jobs
+                    .programs
+                    .get(&process.program)
+                    .and_then(|job| job.processes.get(process.index))
+                {
+                    Some(p) if p.name == process.name => p.status(),
+                    _ => {
+                        socket.send(
+                            *fd,
+                            &Response::Transition {
+                                process: removed(process),
+                            },
+                        );
+                        return false;
+                    }
+                };
+                if process.state != Some((status.state, status.health)) {
+                    process.state = Some((status.state, status.health));
+                    socket.send(*fd, &Response::Transition { process: status });
+                }
+                true
+            });
+        }
+    }
+}
+
+fn removed(process: &Watched) -> ProcessStatus {
+    ProcessStatus {
+        name: process.name.clone(),
+        pid: None,
+        orphans: Vec::new(),
+        state: ProcessState::Stopped,
+        health: None,
+        description: "removed".to_string(),
+        notice: None,
+    }
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use common::{wait_until, Daemon};
+use std::thread;
+use std::time::Duration;
+use taskmasterproto::{ProcessState, Request};
+
+/// A process that writes to stdout all the time, and gets SIGPIPE if nothing can read it
+const CONFIG: &str = r#"
+programs:
+  chatty:
+    cmd: [sh, -c, "while :; do echo tick; sleep 0.05; done"]
+    autostart: true
+    starttime: 1
+"#;
+
+#[test]
+fn test_adopted_keeps_output() {
+    let mut daemon = Daemon::start("adopted-output", CONFIG);
+    assert!(wait_until(
+        || daemon.process("chatty-0").state == ProcessState::Running
+    ));
+    let pid = daemon.process("chatty-0").pid;
+    daemon.crash_and_restart();
+    // long enough for a process without a reader to die writing
+    thread::sleep(Duration::from_millis(500));
+    let process = daemon.process("chatty-0");
+    assert_eq!(process.pid, pid);
+    assert_eq!(process.state, ProcessState::Running);
+    assert!(wait_until(|| daemon
+        .tail("chatty-0")
+        .starts_with(b"tick\n")));
+}
+
+#[test]
+fn test_adopted_keeps_scale() {
+    let mut daemon = Daemon::start("adopted-scale", CONFIG);
+    daemon.request(&Request::Scale {
+        program: "chatty".to_string(),
+        numprocs: 2,
+    });
+    assert!(wait_until(
+        || daemon.process("chatty-1").state == ProcessState::Running
+    ));
+    let pid = daemon.process("chatty-1").pid;
+    daemon.crash_and_restart();
+    // the process past the numprocs of the config is still supervised
+    assert_eq!(daemon.process("chatty-1").pid, pid);
+    daemon.request(&Request::Stop {
+        targets: vec!["chatty-1".to_string()],
+    });
+    assert!(wait_until(
+        || daemon.process("chatty-1").state == ProcessState::Stopped
+    ));
+}
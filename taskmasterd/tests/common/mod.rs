@@ -0,0 +1,179 @@
+//! Daemon started in a home directory of its own, and driven through the control socket
+
+// each test crate uses its own part of the helpers
+#![allow(dead_code)]
+
+use std::fs;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use taskmasterproto::{
+    read_frame, write_frame, ProcessStatus, Request, Response, PROTOCOL_VERSION, SOCKET_PATH,
+};
+
+/// How long a condition is waited for before the test fails
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Daemon {
+    pub home: PathBuf,
+    pub child: Child,
+}
+
+impl Daemon {
+    /// Start a daemon in the foreground with `config`, and wait for it to accept clients
+    pub fn start(name: &str, config: &str) -> Self {
+        let home =
+            std::env::temp_dir().join(format!("taskmasterd-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(&home).unwrap();
+        fs::write(home.join("config.yml"), config).unwrap();
+        let child = spawn(&home);
+        let daemon = Self { home, child };
+        daemon.wait_ready();
+        daemon
+    }
+
+    /// Kill the daemon without letting it stop its processes, and start another one in the
+    /// same home directory
+    pub fn crash_and_restart(&mut self) {
+        self.child.kill().unwrap();
+        self.child.wait().unwrap();
+        self.child = spawn(&self.home);
+        self.wait_ready();
+    }
+
+    fn wait_ready(&self) {
+        assert!(
+            wait_until(|| self.connect().is_some()),
+            "the daemon did not start"
+        );
+    }
+
+    /// Connect and perform the handshake, `None` if the daemon does not answer
+    pub fn connect(&self) -> Option<UnixStream> {
+        let mut stream = UnixStream::connect(self.home.join(SOCKET_PATH)).ok()?;
+        let hello = Request::Hello {
+            version: PROTOCOL_VERSION,
+        };
+        write_frame(&mut stream, &hello).ok()?;
+        match read_frame(&mut stream).ok()?? {
+            Response::Hello { .. } => Some(stream),
+            _ => None,
+        }
+    }
+
+    /// Send a request on a new connection and return the response
+    pub fn request(&self, request: &Request) -> Response {
+        let mut stream = self.connect().expect("the daemon should answer");
+        write_frame(&mut stream, request).unwrap();
+        read_frame(&mut stream).unwrap().unwrap()
+    }
+
+    /// Status of every process
+    pub fn status(&self) -> Vec<ProcessStatus> {
+        let request = Request::Status {
+            targets: Vec::new(),
+        };
+        match self.request(&request) {
+            Response::Status { programs, .. } => {
+                programs.into_iter().flat_map(|p| p.processes).collect()
+            }
+            response => panic!("unexpected response: {:?}", response),
+        }
+    }
+
+    /// Status of a single process
+    pub fn process(&self, name: &str) -> ProcessStatus {
+        self.status()
+            .into_iter()
+            .find(|process| process.name == name)
+            .expect("the process should exist")
+    }
+
+    /// Recent stdout of a single process
+    pub fn tail(&self, name: &str) -> Vec<u8> {
+        let request = Request::Tail {
+            target: name.to_string(),
+            stderr: false,
+            follow: false,
+            lines: usize::MAX,
+        };
+        match self.request(&request) {
+            Response::Output { data, .. } => data,
+            response => panic!("unexpected response: {:?}", response),
+        }
+    }
+
+    /// Wait for the daemon to exit, returns whether it did in time
+    pub fn wait_exit(&mut self) -> bool {
+        wait_until(|| self.child.try_wait().unwrap().is_some())
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        if self.child.try_wait().unwrap().is_none() {
+            if let Some(mut stream) = self.connect() {
+                let _ = write_frame(&mut stream, &Request::Shutdown);
+            }
+            if !self.wait_exit() {
+                let _ = self.child.kill();
+                let _ = self.child.wait();
+            }
+        }
+        let _ = fs::remove_dir_all(&self.home);
+    }
+}
+
+fn spawn(home: &Path) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_taskmasterd"))
+        .arg("--nodaemon")
+        .env("HOME", home)
+        .current_dir(home)
+        .stdout(Stdio::null())
+        .stderr(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(home.join("stderr"))
+                .unwrap(),
+        )
+        .spawn()
+        .unwrap()
+}
+
+/// Poll `condition` until it holds, returns `false` if it did not in time
+pub fn wait_until(mut condition: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        if condition() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+/// Pids of the live members of the process group `pgid`, zombies excluded
+pub fn group_members(pgid: i32) -> Vec<i32> {
+    let mut members = Vec::new();
+    for entry in fs::read_dir("/proc").unwrap().flatten() {
+        let Ok(pid) = entry.file_name().to_string_lossy().parse::<i32>() else {
+            continue;
+        };
+        let Ok(stat) = fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        // the fields after the command name, which may hold spaces, start with the state
+        let Some((_, fields)) = stat.rsplit_once(')') else {
+            continue;
+        };
+        let fields = fields.split_whitespace().collect::<Vec<_>>();
+        if fields[0] != "Z" && fields[2].parse() == Ok(pgid) {
+            members.push(pid);
+        }
+    }
+    members
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::{wait_until, Daemon};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+use taskmasterproto::Health;
+
+#[test]
+fn test_http_check_answered_later() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0; 1024];
+        let n = stream.read(&mut request).unwrap();
+        assert!(request[..n].starts_with(b"GET / HTTP/1.0\r\n"));
+        // the daemon waits for the response without polling until the timeout
+        thread::sleep(Duration::from_millis(300));
+        stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n").unwrap();
+    });
+    let config = format!(
+        r#"
+programs:
+  web:
+    cmd: [sleep, "100"]
+    autostart: true
+    starttime: 1
+    healthcheck:
+      type: http
+      port: {}
+      timeout: 60
+      interval: 60
+"#,
+        port
+    );
+    let daemon = Daemon::start("http-check", &config);
+    assert!(wait_until(
+        || daemon.process("web-0").health == Some(Health::Healthy)
+    ));
+    server.join().unwrap();
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use common::{group_members, wait_until, Daemon};
+use taskmasterproto::{ProcessState, Request};
+
+/// A wrapper whose background child ignores SIGTERM, so it outlives the stop signal and has
+/// to be killed with the process group
+const CONFIG: &str = r#"
+programs:
+  wrapper:
+    cmd: [sh, -c, "(trap '' TERM; while :; do sleep 0.1; done) & exec sleep 100"]
+    autostart: true
+    starttime: 1
+    stopasgroup: true
+    stoptimeout: 1
+"#;
+
+/// Wait for the wrapper to run along with its background child, and return its pgid
+fn running_group(daemon: &Daemon) -> i32 {
+    assert!(wait_until(|| daemon.process("wrapper-0").pid.is_some()));
+    let pgid = daemon.process("wrapper-0").pid.unwrap();
+    assert!(wait_until(|| group_members(pgid).len() >= 2));
+    pgid
+}
+
+#[test]
+fn test_stop_kills_group() {
+    let daemon = Daemon::start("stop-group", CONFIG);
+    let pgid = running_group(&daemon);
+    daemon.request(&Request::Stop {
+        targets: Vec::new(),
+    });
+    assert!(wait_until(|| group_members(pgid).is_empty()));
+    assert_eq!(daemon.process("wrapper-0").state, ProcessState::Stopped);
+}
+
+#[test]
+fn test_shutdown_waits_for_group() {
+    let mut daemon = Daemon::start("shutdown-group", CONFIG);
+    let pgid = running_group(&daemon);
+    daemon.request(&Request::Shutdown);
+    assert!(daemon.wait_exit());
+    // the member ignoring SIGTERM would outlive a daemon that did not wait for it
+    let members = group_members(pgid);
+    for pid in members.iter() {
+        let _ = std::process::Command::new("kill")
+            .args(["-9", &pid.to_string()])
+            .status();
+    }
+    assert!(members.is_empty(), "left alive: {:?}", members);
+}
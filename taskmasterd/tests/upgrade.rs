@@ -0,0 +1,118 @@
+mod common;
+
+use common::{wait_until, Daemon};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::fs;
+use std::path::Path;
+use taskmasterproto::{ErrorCode, ProcessState, Request, Response};
+
+const CONFIG: &str = r#"
+programs:
+  sleeper:
+    cmd: [sleep, "100"]
+    autostart: true
+    starttime: 1
+    numprocs: 2
+"#;
+
+/// Upgrade the daemon and wait for the new image to answer
+fn upgrade(daemon: &Daemon) {
+    assert_eq!(daemon.request(&Request::Upgrade), Response::Ok);
+    let log = daemon.home.join("stderr");
+    assert!(wait_until(|| fs::read_to_string(&log)
+        .unwrap()
+        .contains("Took over from the previous daemon")));
+    assert!(wait_until(|| daemon.connect().is_some()));
+}
+
+/// Pids of the sleepers once they run
+fn running(daemon: &Daemon) -> Vec<i32> {
+    let names = ["sleeper-0", "sleeper-1"];
+    assert!(wait_until(|| {
+        names
+            .iter()
+            .all(|name| daemon.process(name).state == ProcessState::Running)
+    }));
+    names
+        .iter()
+        .map(|name| daemon.process(name).pid.unwrap())
+        .collect()
+}
+
+#[test]
+fn test_upgrade_keeps_processes() {
+    let mut daemon = Daemon::start("upgrade", CONFIG);
+    let pids = running(&daemon);
+    upgrade(&daemon);
+    assert_eq!(running(&daemon), pids);
+    assert!(daemon.child.try_wait().unwrap().is_none());
+
+    // the new image still reaps them, and notices when they exit
+    kill(Pid::from_raw(pids[0]), Signal::SIGKILL).unwrap();
+    assert!(wait_until(
+        || daemon.process("sleeper-0").pid != Some(pids[0])
+    ));
+    daemon.request(&Request::Stop {
+        targets: vec!["sleeper-1".to_string()],
+    });
+    assert!(wait_until(
+        || daemon.process("sleeper-1").state == ProcessState::Stopped
+    ));
+    assert!(!Path::new(&format!("/proc/{}", pids[1])).exists());
+}
+
+#[test]
+fn test_upgrade_refused_while_rolling() {
+    let mut daemon = Daemon::start("upgrade-rolling", CONFIG);
+    running(&daemon);
+    daemon.request(&Request::Restart {
+        targets: vec!["sleeper".to_string()],
+        rolling: Some(true),
+        batch: Some(1),
+        pause: Some(60),
+    });
+    // the second process waits for the pause after the first one
+    assert!(matches!(
+        daemon.request(&Request::Upgrade),
+        Response::Error {
+            code: ErrorCode::InvalidRequest,
+            ..
+        }
+    ));
+    assert!(daemon.child.try_wait().unwrap().is_none());
+}
+
+#[test]
+fn test_upgrade_refused_while_queued() {
+    let config = r#"
+programs:
+  db:
+    cmd: [sleep, "100"]
+    starttime: 5
+  app:
+    cmd: [sleep, "100"]
+    depends_on: [db]
+"#;
+    let mut daemon = Daemon::start("upgrade-queued", config);
+    daemon.request(&Request::Start {
+        targets: vec!["app".to_string()],
+    });
+    // app waits for db to be running
+    assert_eq!(daemon.process("db-0").state, ProcessState::Starting);
+    assert_eq!(daemon.process("app-0").state, ProcessState::Stopped);
+    assert!(matches!(
+        daemon.request(&Request::Upgrade),
+        Response::Error {
+            code: ErrorCode::InvalidRequest,
+            ..
+        }
+    ));
+    assert!(daemon.child.try_wait().unwrap().is_none());
+
+    // once the queue is empty the upgrade goes through
+    assert!(wait_until(
+        || daemon.process("app-0").state == ProcessState::Running
+    ));
+    upgrade(&daemon);
+}
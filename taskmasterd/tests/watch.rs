@@ -0,0 +1,40 @@
+mod common;
+
+use common::Daemon;
+use std::time::Duration;
+use taskmasterproto::{read_frame, write_frame, ProcessState, Request, Response};
+
+const CONFIG: &str = r#"
+programs:
+  sleeper:
+    cmd: [sleep, "100"]
+    autostart: true
+    numprocs: 2
+"#;
+
+#[test]
+fn test_watch_removed_process() {
+    let daemon = Daemon::start("watch-removed", CONFIG);
+    let mut stream = daemon.connect().unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let watch = Request::Watch {
+        targets: vec!["sleeper-1".to_string()],
+    };
+    write_frame(&mut stream, &watch).unwrap();
+    daemon.request(&Request::Scale {
+        program: "sleeper".to_string(),
+        numprocs: 1,
+    });
+    // the watch ends with the removal, instead of leaving the client waiting
+    let last = loop {
+        match read_frame(&mut stream).unwrap().unwrap() {
+            Response::Transition { process } if process.description == "removed" => break process,
+            Response::Transition { .. } => {}
+            response => panic!("unexpected response: {:?}", response),
+        }
+    };
+    assert_eq!(last.name, "sleeper-1");
+    assert_eq!(last.state, ProcessState::Stopped);
+}
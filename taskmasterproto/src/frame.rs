@@ -0,0 +1,103 @@
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{ErrorKind, Read, Write};
+
+/// Largest accepted frame, in bytes
+pub const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+const HEADER_LEN: usize = std::mem::size_of::<u32>();
+
+/// Write `message` as a single frame
+pub fn write_frame<T: Serialize>(writer: &mut impl Write, message: &T) -> Result<()> {
+    let payload = serde_json::to_vec(message).context("Failed to serialize message")?;
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME_LEN)
+        .context("Message is too large")?;
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&payload);
+    writer.write_all(&frame).context("Failed to write frame")?;
+    Ok(())
+}
+
+/// Read a single frame, blocking until it is complete
+///
+/// Returns `None` if the stream was closed before a new frame started.
+pub fn read_frame<T: DeserializeOwned>(reader: &mut impl Read) -> Result<Option<T>> {
+    let mut header = [0; HEADER_LEN];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e).context("Failed to read frame header"),
+    }
+    let len = u32::from_be_bytes(header);
+    if len > MAX_FRAME_LEN {
+        bail!("Frame of {} bytes is too large", len);
+    }
+    let mut payload = vec![0; len as usize];
+    reader
+        .read_exact(&mut payload)
+        .context("Failed to read frame payload")?;
+    let message = serde_json::from_slice(&payload).context("Invalid message")?;
+    Ok(Some(message))
+}
+
+/// Remove the first complete frame from `buffer` and return its payload
+///
+/// Returns `None` if `buffer` does not hold a complete frame yet.
+pub fn take_frame(buffer: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+    if buffer.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    let len = u32::from_be_bytes(buffer[..HEADER_LEN].try_into()?);
+    if len > MAX_FRAME_LEN {
+        bail!("Frame of {} bytes is too large", len);
+    }
+    let end = HEADER_LEN + len as usize;
+    if buffer.len() < end {
+        return Ok(None);
+    }
+    let payload = buffer[HEADER_LEN..end].to_vec();
+    buffer.drain(..end);
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Request;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, &Request::Hello { version: 1 }).unwrap();
+        write_frame(&mut buffer, &Request::Shutdown).unwrap();
+        let mut reader = buffer.as_slice();
+        assert_eq!(
+            read_frame::<Request>(&mut reader).unwrap(),
+            Some(Request::Hello { version: 1 })
+        );
+        assert_eq!(
+            read_frame::<Request>(&mut reader).unwrap(),
+            Some(Request::Shutdown)
+        );
+        assert_eq!(read_frame::<Request>(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_take_partial_frame() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, &Request::Shutdown).unwrap();
+        let mut buffer = frame[..frame.len() - 1].to_vec();
+        assert_eq!(take_frame(&mut buffer).unwrap(), None);
+        buffer.push(frame[frame.len() - 1]);
+        let payload = take_frame(&mut buffer).unwrap().unwrap();
+        assert!(buffer.is_empty());
+        assert_eq!(
+            serde_json::from_slice::<Request>(&payload).unwrap(),
+            Request::Shutdown
+        );
+    }
+}
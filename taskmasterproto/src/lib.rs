@@ -0,0 +1,25 @@
+//! Control protocol spoken between `taskmasterd` and `taskmasterctl`
+//!
+//! Every message is a frame made of a big-endian `u32` length followed by that many bytes of
+//! JSON. A connection starts with a [`Request::Hello`] carrying the client's
+//! [`PROTOCOL_VERSION`], after which any number of requests can be sent, each answered by
+//! exactly one [`Response`], except for [`Request::Watch`], [`Request::Attach`] and a
+//! following [`Request::Tail`] which are answered by a stream of responses, and for
+//! [`Request::Input`] which is only answered on failure.
+//!
+//! Commands acting on processes take a list of targets, each one being `all`, a program name,
+//! a process name (`name-3`), every process of a program (`name:*`), every process of a group
+//! (`group:`), a program or process of a group (`group:name`) or a glob (`web*`). An empty list
+//! of targets means `all`.
+
+mod frame;
+mod message;
+
+pub use frame::{read_frame, take_frame, write_frame, MAX_FRAME_LEN};
+pub use message::*;
+
+/// Version of the protocol, bumped on every incompatible change
+pub const PROTOCOL_VERSION: u32 = 17;
+
+/// Path of the control socket, relative to the home directory
+pub const SOCKET_PATH: &str = ".taskmasterd/taskmasterd.sock";
@@ -0,0 +1,378 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Request sent by the client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum Request {
+    /// First request of every connection
+    Hello { version: u32 },
+    /// Start the targeted processes
+    Start { targets: Vec<String> },
+    /// Stop the targeted processes
+    Stop { targets: Vec<String> },
+    /// Restart the targeted processes, all at once or in batches
+    Restart {
+        targets: Vec<String>,
+        /// Whether to restart in batches, defaults to whether the program has a `rolling`
+        /// section
+        #[serde(default)]
+        rolling: Option<bool>,
+        /// Processes restarted at once by a rolling restart, instead of the configured batch
+        #[serde(default)]
+        batch: Option<u32>,
+        /// Seconds to wait between the batches, instead of the configured pause
+        #[serde(default)]
+        pause: Option<u64>,
+    },
+    /// Get the status of the targeted processes
+    Status { targets: Vec<String> },
+    /// Send a signal, like `TERM` or `SIGUSR1`, to the targeted processes
+    Signal {
+        signal: String,
+        targets: Vec<String>,
+    },
+    /// Rotate the log files of the targeted processes, or only reopen them if `reopen` is
+    /// set, after they were moved by another program
+    Rotate { targets: Vec<String>, reopen: bool },
+    /// Follow the state of the targeted processes
+    ///
+    /// Answered by a [`Response::Transition`] with the current state of each process, then
+    /// by one more every time one of them changes state, until the client disconnects.
+    Watch { targets: Vec<String> },
+    /// Get the last `lines` lines of output of a single process, from stderr if `stderr` is
+    /// set and from stdout otherwise
+    ///
+    /// Answered by a [`Response::Output`], then if `follow` is set by one more every time the
+    /// process writes to the stream, until the client disconnects.
+    Tail {
+        target: String,
+        stderr: bool,
+        follow: bool,
+        lines: usize,
+    },
+    /// Attach to the stdin, stdout and stderr of a single process
+    ///
+    /// Answered by a [`Response::Attached`], then by a [`Response::Output`] every time the
+    /// process writes to stdout or stderr, until the client disconnects. The client can then
+    /// send [`Request::Input`].
+    Attach { target: String },
+    /// Write to the stdin of the attached process, only answered if it fails
+    Input {
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    /// Set the number of processes of `program`, only starting or stopping the difference
+    Scale { program: String, numprocs: u32 },
+    /// Load the configuration file at `path`, applying the changes like [`Request::Update`]
+    Load { path: PathBuf },
+    /// Read the configuration file again and tell which programs changed, without acting
+    Reread,
+    /// Tell what loading the configuration file at `path` would change, without acting
+    Diff { path: PathBuf },
+    /// Read the configuration file again and apply the changes: the added programs are
+    /// started, the changed and removed ones are stopped then replaced or removed, and the
+    /// others are left as they are
+    Update,
+    /// Shutdown the daemon
+    Shutdown,
+    /// Replace the daemon with a new image of its executable, without stopping the processes
+    ///
+    /// Answered before the exec, the connection is then closed.
+    Upgrade,
+}
+
+/// Response sent by the daemon, one per request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum Response {
+    /// Handshake accepted
+    Hello { version: u32 },
+    /// The request was carried out
+    Ok,
+    /// Outcome of the request for each of its targets
+    Results { results: Vec<TargetResult> },
+    /// Status of the targeted processes, grouped by program
+    Status {
+        programs: Vec<ProgramStatus>,
+        /// Targets that did not match any process
+        not_found: Vec<String>,
+    },
+    /// A watched process changed state
+    Transition { process: ProcessStatus },
+    /// Output of a tailed or attached process, the bytes as it wrote them
+    Output {
+        process: String,
+        stderr: bool,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    /// Programs that differ between the running configuration and the configuration file
+    Diff(Box<ConfigDiff>),
+    /// The client is attached to the process, `tty` telling if it runs on a pseudo-terminal
+    Attached { process: String, tty: bool },
+    /// The request failed
+    Error { code: ErrorCode, message: String },
+}
+
+/// Programs and groups added, changed and removed by a configuration file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigDiff {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    #[serde(default)]
+    pub added_groups: Vec<String>,
+    /// Groups whose member programs changed
+    #[serde(default)]
+    pub changed_groups: Vec<String>,
+    #[serde(default)]
+    pub removed_groups: Vec<String>,
+    /// Description of the fields that changed, by changed program, like `numprocs: 2 -> 4`
+    pub fields: BTreeMap<String, Vec<String>>,
+    /// Running processes that would be restarted, because their program changed
+    pub restart: Vec<String>,
+    /// Running processes that would be stopped, because their program was removed
+    pub stop: Vec<String>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.changed.is_empty()
+            && self.removed.is_empty()
+            && self.added_groups.is_empty()
+            && self.changed_groups.is_empty()
+            && self.removed_groups.is_empty()
+    }
+}
+
+/// Reason a request failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The client speaks another version of the protocol
+    VersionMismatch,
+    /// A request was sent before the handshake
+    HandshakeRequired,
+    /// The request could not be decoded
+    InvalidRequest,
+    /// The requested program does not exist
+    NotFound,
+    /// The configuration file could not be loaded
+    InvalidConfig,
+    /// The daemon failed to carry out the request
+    Internal,
+}
+
+/// Outcome of a request for one target
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetResult {
+    pub target: String,
+    /// Outcome for each process selected by the target
+    pub processes: Vec<ProcessResult>,
+    /// Set when the target could not be resolved to any process
+    pub error: Option<TargetError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessResult {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+/// Reason a target could not be resolved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "error", rename_all = "snake_case")]
+pub enum TargetError {
+    /// No process matches the target
+    NotFound,
+    /// The target is not a valid selector
+    Invalid { reason: String },
+}
+
+/// Outcome of a request for one process
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    /// The request was carried out
+    Ok,
+    /// The process will be restarted when its turn comes in a rolling restart
+    Queued,
+    /// The process could not be started because it is already running
+    AlreadyRunning,
+    /// The process could not be stopped or signaled because it is not running
+    NotRunning,
+    /// The process could not be spawned
+    SpawnError { reason: String },
+    /// The request failed for another reason
+    Error { reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgramStatus {
+    pub name: String,
+    /// Group the program is a member of
+    #[serde(default)]
+    pub group: Option<String>,
+    pub processes: Vec<ProcessStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessStatus {
+    pub name: String,
+    pub pid: Option<i32>,
+    /// Descendants that outlived their parent and were adopted by the daemon
+    #[serde(default)]
+    pub orphans: Vec<i32>,
+    pub state: ProcessState,
+    /// Result of the health checks of a running process, if it has some
+    #[serde(default)]
+    pub health: Option<Health>,
+    /// Human readable state, with its details
+    pub description: String,
+    /// Status last sent by a running process configured with `notify`, with `STATUS=`
+    #[serde(default)]
+    pub notice: Option<String>,
+}
+
+/// State of a process, as shown to clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessState {
+    /// Started, but not for long enough to be considered running
+    Starting,
+    /// Running past its start time
+    Running,
+    /// Asked to stop, waiting for it to exit
+    Stopping,
+    /// Exited before being fully started, waiting to be retried
+    Backoff,
+    /// Could not be started, even after retrying
+    Fatal,
+    /// Exited with an unexpected exit code
+    Unexpected,
+    /// Exited with an expected exit code
+    Exited,
+    /// Stopped or never started
+    Stopped,
+}
+
+/// Health of a running process, as told by its health checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    /// No check succeeded yet, and none failed after the start period
+    Starting,
+    /// The last check succeeded
+    Healthy,
+    /// The last check failed
+    Unhealthy,
+}
+
+impl ProcessState {
+    /// Whether the process is alive
+    pub fn is_running(&self) -> bool {
+        matches!(
+            self,
+            ProcessState::Starting | ProcessState::Running | ProcessState::Stopping
+        )
+    }
+}
+
+impl Response {
+    pub fn error(code: ErrorCode, message: impl ToString) -> Self {
+        Self::Error {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl TargetResult {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+            && self
+                .processes
+                .iter()
+                .all(|p| matches!(p.outcome, Outcome::Ok | Outcome::Queued))
+    }
+}
+
+impl std::fmt::Display for TargetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetError::NotFound => write!(f, "no such process"),
+            TargetError::Invalid { reason } => write!(f, "invalid target: {}", reason),
+        }
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Ok => write!(f, "ok"),
+            Outcome::Queued => write!(f, "queued"),
+            Outcome::AlreadyRunning => write!(f, "already running"),
+            Outcome::NotRunning => write!(f, "not running"),
+            Outcome::SpawnError { reason } => write!(f, "spawn error: {}", reason),
+            Outcome::Error { reason } => write!(f, "error: {}", reason),
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorCode::VersionMismatch => write!(f, "protocol version mismatch"),
+            ErrorCode::HandshakeRequired => write!(f, "handshake required"),
+            ErrorCode::InvalidRequest => write!(f, "invalid request"),
+            ErrorCode::NotFound => write!(f, "not found"),
+            ErrorCode::InvalidConfig => write!(f, "invalid config"),
+            ErrorCode::Internal => write!(f, "internal error"),
+        }
+    }
+}
+
+/// Bytes carried as a base64 string, since JSON strings only hold valid UTF-8
+mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_bytes() {
+        // half of a multibyte character, an escape sequence and invalid UTF-8
+        let data = vec![0xc3, 0x1b, b'[', b'2', b'J', 0xff, 0x00];
+        let response = Response::Output {
+            process: "web-0".to_string(),
+            stderr: false,
+            data: data.clone(),
+        };
+        let json = serde_json::to_string(&response).unwrap();
+        assert!(json.contains("\"data\":\"wxtbMkr/AA==\""));
+        assert_eq!(serde_json::from_str::<Response>(&json).unwrap(), response);
+        let input = serde_json::from_str::<Request>(r#"{"request":"input","data":"aGk="}"#);
+        assert_eq!(
+            input.unwrap(),
+            Request::Input {
+                data: b"hi".to_vec()
+            }
+        );
+    }
+}